@@ -0,0 +1,452 @@
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const CONFIG_DIR_NAME: &str = "repos";
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+const DEV_DIR_ENV_VAR: &str = "DEVDIR";
+const UPDATE_DELAY_SECS: f32 = 0.5;
+const REPO_NAME_WIDTH: u16 = 35;
+const MAX_BRANCH_NAME_WIDTH: usize = 30;
+const INCLUDE_IGNORED: bool = false;
+const MAX_SEARCH_DEPTH: usize = 3;
+const AHEAD_BEHIND_ALL_BRANCHES: bool = false;
+const WORKERS: usize = 8;
+const AUTO_STASH: bool = false;
+const LOG_LENGTH: usize = 10;
+
+/*
+Example config:
+
+    update_delay_secs = 1.0
+    exclude = ["node_modules", "target"]
+
+    [roots."~/dev/clients"]
+    depth = 4
+
+    [repos."~/dev/work/api"]
+    trunk = "develop"
+
+    [workspaces.work]
+    roots = ["~/dev/work", "~/dev/infra"]
+    repos = ["~/src/shared-lib"]
+
+    [[open_actions]]
+    name = "editor"
+    command = ["code", "{path}"]
+*/
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Env var with the roots, used when there are no roots in the args.
+    pub dev_dir_env_var: String,
+    pub update_delay_secs: f32,
+    pub repo_name_width: u16,
+    pub max_branch_name_width: usize,
+    pub include_ignored: bool,
+    // How many directory levels below a root are searched for repos.
+    pub depth: usize,
+    // Globs of directories that are not searched.
+    pub exclude: Vec<String>,
+    // Show ahead/behind for every local branch, not only the current one.
+    pub ahead_behind_all_branches: bool,
+    // How many repos are fetched, pulled... at the same time.
+    pub workers: usize,
+    // Stash the local changes when leaving a branch, and apply them when coming back.
+    pub auto_stash: bool,
+    // How many commits the log pane shows.
+    pub log_length: usize,
+    // Commands for opening a repo, picked from a menu when there are more than one.
+    pub open_actions: Vec<OpenAction>,
+    // Overrides for the repos under a root.
+    pub roots: BTreeMap<PathBuf, RootConfig>,
+    // Overrides for single repos.
+    pub repos: BTreeMap<PathBuf, RepoConfig>,
+    pub workspaces: BTreeMap<String, Workspace>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RootConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_ignored: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepoConfig {
+    // The main branch of the repo, detected when not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trunk: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Workspace {
+    // Dirs searched for repos.
+    pub roots: Vec<PathBuf>,
+    // Repos listed one by one.
+    pub repos: Vec<PathBuf>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OpenAction {
+    pub name: String,
+    // The program and its arguments, "{path}", "{name}" and "{branch}" are replaced with the repo's.
+    pub command: Vec<String>,
+}
+
+impl OpenAction {
+    pub fn command_for(&self, path: &Path, name: &str, branch: &str) -> Vec<String> {
+        self.command
+            .iter()
+            .map(|arg| {
+                arg.replace("{path}", &path.to_string_lossy())
+                    .replace("{name}", name)
+                    .replace("{branch}", branch)
+            })
+            .collect()
+    }
+}
+
+// Values from the command line, they win over the config file.
+#[derive(Default)]
+pub struct ConfigOverrides {
+    pub dev_dir_env_var: Option<String>,
+    pub update_delay_secs: Option<f32>,
+    pub repo_name_width: Option<u16>,
+    pub max_branch_name_width: Option<usize>,
+    pub include_ignored: Option<bool>,
+    pub depth: Option<usize>,
+    pub exclude: Option<Vec<String>>,
+    pub ahead_behind_all_branches: Option<bool>,
+    pub workers: Option<usize>,
+    pub auto_stash: Option<bool>,
+    pub log_length: Option<usize>,
+}
+
+// The settings a single repo is read with.
+#[derive(Clone)]
+pub struct RepoSettings {
+    pub include_ignored: bool,
+    pub trunk: Option<String>,
+    pub ahead_behind_all_branches: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            dev_dir_env_var: DEV_DIR_ENV_VAR.to_string(),
+            update_delay_secs: UPDATE_DELAY_SECS,
+            repo_name_width: REPO_NAME_WIDTH,
+            max_branch_name_width: MAX_BRANCH_NAME_WIDTH,
+            include_ignored: INCLUDE_IGNORED,
+            depth: MAX_SEARCH_DEPTH,
+            exclude: vec![],
+            ahead_behind_all_branches: AHEAD_BEHIND_ALL_BRANCHES,
+            workers: WORKERS,
+            auto_stash: AUTO_STASH,
+            log_length: LOG_LENGTH,
+            open_actions: vec![OpenAction {
+                name: "terminal".to_string(),
+                command: vec!["gnome-terminal".to_string(), "--working-directory={path}".to_string()],
+            }],
+            roots: BTreeMap::new(),
+            repos: BTreeMap::new(),
+            workspaces: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    // Without an explicit path a missing config file is not an error,
+    // the defaults are used.
+    pub fn load(path: Option<&Path>) -> ReposResult<Self> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => match default_config_path() {
+                Some(path) if path.is_file() => path,
+                _ => return Ok(Self::default()),
+            },
+        };
+        let text = std::fs::read_to_string(&path).map_err(|error| {
+            Error::other(format!(r#"Could not read config "{}": {}"#, path.display(), error))
+        })?;
+        Self::parse(&text).map_err(|error| {
+            Error::other(format!(r#"Invalid config "{}": {}"#, path.display(), error)).into()
+        })
+    }
+
+    pub fn parse(text: &str) -> ReposResult<Self> {
+        let config: Self = toml::from_str(text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // The values that the types allow but that would break the loops,
+    // like a negative delay, which makes the Duration panic.
+    fn validate(&self) -> ReposResult<()> {
+        if !self.update_delay_secs.is_finite() || self.update_delay_secs < 0.0 {
+            return Err(Box::new(Error::other(format!(
+                "update_delay_secs must be 0 or more seconds, not {}",
+                self.update_delay_secs
+            ))));
+        }
+        for (name, value) in [
+            ("repo_name_width", self.repo_name_width as usize),
+            ("max_branch_name_width", self.max_branch_name_width),
+            ("workers", self.workers),
+        ] {
+            if value == 0 {
+                return Err(Box::new(Error::other(format!("{} must be 1 or more", name))));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> ReposResult<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    pub fn apply_overrides(&mut self, overrides: ConfigOverrides) -> ReposResult<()> {
+        if let Some(value) = overrides.dev_dir_env_var {
+            self.dev_dir_env_var = value;
+        }
+        if let Some(value) = overrides.update_delay_secs {
+            self.update_delay_secs = value;
+        }
+        if let Some(value) = overrides.repo_name_width {
+            self.repo_name_width = value;
+        }
+        if let Some(value) = overrides.max_branch_name_width {
+            self.max_branch_name_width = value;
+        }
+        if let Some(value) = overrides.ahead_behind_all_branches {
+            self.ahead_behind_all_branches = value;
+        }
+        if let Some(value) = overrides.workers {
+            self.workers = value;
+        }
+        if let Some(value) = overrides.auto_stash {
+            self.auto_stash = value;
+        }
+        if let Some(value) = overrides.log_length {
+            self.log_length = value;
+        }
+        // The keys that can be set per root are removed from the roots,
+        // so the command line value is used everywhere.
+        if let Some(value) = overrides.include_ignored {
+            self.include_ignored = value;
+            self.roots.values_mut().for_each(|r| r.include_ignored = None);
+        }
+        if let Some(value) = overrides.depth {
+            self.depth = value;
+            self.roots.values_mut().for_each(|r| r.depth = None);
+        }
+        if let Some(value) = overrides.exclude {
+            self.exclude = value;
+            self.roots.values_mut().for_each(|r| r.exclude = None);
+        }
+        self.validate()
+    }
+
+    pub fn workspace(&self, name: &str) -> ReposResult<&Workspace> {
+        self.workspaces
+            .get(name)
+            .ok_or_else(|| Error::other(format!(r#"No workspace named "{}" in the config."#, name)).into())
+    }
+
+    // The most specific root the path is in.
+    fn root_config(&self, path: &Path) -> Option<&RootConfig> {
+        self.roots
+            .iter()
+            .map(|(root, config)| (normalize_path(root), config))
+            .filter(|(root, _)| path.starts_with(root))
+            .max_by_key(|(root, _)| root.components().count())
+            .map(|(_, config)| config)
+    }
+
+    pub fn search_options(&self, root: &Path) -> ReposResult<SearchOptions> {
+        let root_config = self.root_config(root);
+        let max_depth = root_config.and_then(|r| r.depth).unwrap_or(self.depth);
+        let excludes = root_config
+            .and_then(|r| r.exclude.as_ref())
+            .unwrap_or(&self.exclude)
+            .iter()
+            .map(|pattern| glob::Pattern::new(pattern))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SearchOptions { max_depth, excludes })
+    }
+
+    pub fn repo_settings(&self, repo_path: &Path) -> RepoSettings {
+        let root_config = self.root_config(repo_path);
+        let repo_config = self
+            .repos
+            .iter()
+            .find(|(path, _)| normalize_path(path) == repo_path)
+            .map(|(_, config)| config);
+        RepoSettings {
+            include_ignored: root_config
+                .and_then(|r| r.include_ignored)
+                .unwrap_or(self.include_ignored),
+            trunk: repo_config.and_then(|r| r.trunk.clone()),
+            ahead_behind_all_branches: self.ahead_behind_all_branches,
+        }
+    }
+}
+
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_dir = match var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(var("HOME").ok()?).join(".config"),
+    };
+    Some(config_dir.join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME))
+}
+
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), var("HOME")) {
+        (Ok(rest), Ok(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_owned(),
+    }
+}
+
+// Paths in the config are compared to canonical paths.
+fn normalize_path(path: &Path) -> PathBuf {
+    let path = expand_home(path);
+    path.canonicalize().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parsing_workspaces() {
+        let config = Config::parse(
+            r#"
+            [workspaces.work]
+            roots = ["~/dev/work", "/srv/infra"]
+            repos = ["/opt/shared"]
+
+            [workspaces.oss]
+            roots = ["~/dev/oss"]
+            "#,
+        )
+        .unwrap();
+        let work = config.workspace("work").unwrap();
+        assert_eq!(work.roots, vec![PathBuf::from("~/dev/work"), PathBuf::from("/srv/infra")]);
+        assert_eq!(work.repos, vec![PathBuf::from("/opt/shared")]);
+        assert!(config.workspace("oss").unwrap().repos.is_empty());
+        assert!(config.workspace("missing").is_err());
+    }
+
+    #[test]
+    fn using_defaults_for_missing_keys() {
+        let config = Config::parse("repo_name_width = 20").unwrap();
+        assert_eq!(config.repo_name_width, 20);
+        assert_eq!(config.max_branch_name_width, MAX_BRANCH_NAME_WIDTH);
+        assert_eq!(config.dev_dir_env_var, DEV_DIR_ENV_VAR);
+        assert!(Config::parse("no_such_key = 1").is_err());
+    }
+
+    #[test]
+    fn rejecting_invalid_values() {
+        let error = |text: &str| Config::parse(text).err().unwrap().to_string();
+        assert_eq!(error("update_delay_secs = -1.0"), "update_delay_secs must be 0 or more seconds, not -1");
+        assert!(Config::parse("update_delay_secs = nan").is_err());
+        assert!(Config::parse("update_delay_secs = 0.0").is_ok());
+        assert_eq!(error("workers = 0"), "workers must be 1 or more");
+        assert!(Config::parse("repo_name_width = 0").is_err());
+        assert!(Config::parse("max_branch_name_width = 0").is_err());
+
+        let mut config = Config::default();
+        let overrides = ConfigOverrides {
+            update_delay_secs: Some(f32::NAN),
+            ..Default::default()
+        };
+        assert!(config.apply_overrides(overrides).is_err());
+    }
+
+    #[test]
+    fn overriding_settings_per_root() {
+        let mut config = Config::parse(
+            r#"
+            depth = 2
+            exclude = ["target"]
+
+            [roots."/dev/clients"]
+            depth = 4
+            include_ignored = true
+
+            [roots."/dev/clients/acme"]
+            exclude = ["node_modules"]
+            "#,
+        )
+        .unwrap();
+        let search = config.search_options(Path::new("/dev/clients/acme")).unwrap();
+        assert_eq!(search.max_depth, 2);
+        assert_eq!(search.excludes, vec![glob::Pattern::new("node_modules").unwrap()]);
+        assert_eq!(config.search_options(Path::new("/dev/clients")).unwrap().max_depth, 4);
+        assert!(config.repo_settings(Path::new("/dev/clients/x/repo")).include_ignored);
+        assert!(!config.repo_settings(Path::new("/dev/other/repo")).include_ignored);
+        assert_eq!(config.repo_settings(Path::new("/dev/other/repo")).trunk, None);
+
+        config.apply_overrides(ConfigOverrides {
+            include_ignored: Some(false),
+            depth: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(config.search_options(Path::new("/dev/clients")).unwrap().max_depth, 1);
+        assert!(!config.repo_settings(Path::new("/dev/clients/x/repo")).include_ignored);
+    }
+
+    #[test]
+    fn overriding_trunk_per_repo() {
+        let config = Config::parse(
+            r#"
+            [repos."/dev/work/api"]
+            trunk = "develop"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.repo_settings(Path::new("/dev/work/api")).trunk.as_deref(), Some("develop"));
+        assert_eq!(config.repo_settings(Path::new("/dev/work/api/sub")).trunk, None);
+    }
+
+    #[test]
+    fn filling_in_open_actions() {
+        assert_eq!(Config::default().open_actions[0].command[0], "gnome-terminal");
+        let config = Config::parse(
+            r#"
+            [[open_actions]]
+            name = "tmux"
+            command = ["tmux", "new-window", "-c", "{path}", "-n", "{name}:{branch}"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.open_actions.len(), 1);
+        let command = config.open_actions[0].command_for(Path::new("/dev/my api"), "my api", "dev");
+        assert_eq!(command, vec!["tmux", "new-window", "-c", "/dev/my api", "-n", "my api:dev"]);
+        assert!(Config::parse("[[open_actions]]\nname = \"x\"").is_err());
+    }
+
+    #[test]
+    fn showing_config_as_toml() {
+        let text = Config::parse(r#"[roots."/dev"]
+depth = 1"#)
+            .unwrap()
+            .to_toml()
+            .unwrap();
+        let config = Config::parse(&text).unwrap();
+        assert_eq!(config.roots[Path::new("/dev")].depth, Some(1));
+        assert_eq!(config.update_delay_secs, UPDATE_DELAY_SECS);
+        assert_eq!(config.open_actions[0].name, "terminal");
+    }
+}
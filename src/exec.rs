@@ -0,0 +1,107 @@
+use crate::prelude::*;
+
+pub struct ExecOptions {
+    // The program and its arguments.
+    pub command: Vec<String>,
+}
+
+// Runs the command in the working directory of the repo.
+// Returns the output, stdout first, then stderr.
+pub fn exec_repo(path: &Path, command: &[String]) -> ReposResult<String> {
+    let (program, arguments) = command
+        .split_first()
+        .ok_or_else(|| Error::other("No command to run."))?;
+    let output = std::process::Command::new(program)
+        .args(arguments)
+        .current_dir(path)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .map_err(|error| Error::other(format!("{}: {}", program, error)))?;
+    let mut text = String::from_utf8_lossy(&output.stdout).to_string();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    let text = text.trim_end().to_string();
+    if output.status.success() {
+        return Ok(text);
+    }
+    let code = match output.status.code() {
+        Some(code) => format!("exit code {}", code),
+        None => "killed by a signal".to_string(),
+    };
+    match text.is_empty() {
+        true => Err(Box::new(Error::other(code))),
+        false => Err(Box::new(Error::other(format!("{}\n{}", code, text)))),
+    }
+}
+
+// Like ("exit code 1", "the output").
+fn result_and_output(state: &JobState) -> (String, String) {
+    match state {
+        JobState::Done(output) => ("ok".to_string(), output.to_string()),
+        JobState::Failed(error) => match error.split_once('\n') {
+            Some((result, output)) => (result.to_string(), output.to_string()),
+            None => (error.to_string(), String::new()),
+        },
+        _ => (state.text(), String::new()),
+    }
+}
+
+// The output of every repo under its name, failed repos in red.
+pub fn exec_popup(results: &[(PathBuf, JobState)]) -> Popup {
+    let mut lines = vec![];
+    for (path, state) in results {
+        let (result, output) = result_and_output(state);
+        lines.push((format!("{}: {}", repo_name(path), result), state.line_style()));
+        let style = match state {
+            JobState::Failed(_) => LineStyle::Error,
+            _ => LineStyle::Plain,
+        };
+        lines.extend(output.lines().map(|line| (format!("  {}", line.replace('\t', "    ")), style)));
+    }
+    Popup::new(&summary("exec", results), lines)
+}
+
+pub fn exec(args: &Args, options: &ExecOptions) -> ReposResult<()> {
+    let paths = find_repo_paths(&args.sources)?;
+    let command = options.command.clone();
+    let job = BatchJob::start("exec", paths, args.config.workers, move |path| exec_repo(path, &command));
+    job.wait(|path, state| {
+        let (result, output) = result_and_output(state);
+        let text = format!("== {}: {}", repo_name(path), result);
+        match state {
+            JobState::Failed(_) => eprintln!("{}", text),
+            _ => println!("{}", text),
+        }
+        if !output.is_empty() {
+            println!("{}", output);
+        }
+    });
+    println!("{}", job.summary());
+    fail_on_job_errors(&job)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn running_a_command_in_a_repo() {
+        let root = tempfile::tempdir().unwrap();
+        let command = |text: &str| text.split(' ').map(String::from).collect::<Vec<_>>();
+        std::fs::write(root.path().join("file"), "content\n").unwrap();
+
+        assert_eq!(exec_repo(root.path(), &command("cat file")).unwrap(), "content");
+        let error = exec_repo(root.path(), &command("cat missing")).unwrap_err().to_string();
+        assert!(error.starts_with("exit code 1\ncat: missing"), "{}", error);
+        let error = exec_repo(root.path(), &command("no-such-program")).unwrap_err().to_string();
+        assert!(error.starts_with("no-such-program: "), "{}", error);
+
+        let results = vec![
+            (root.path().join("api"), JobState::Done("a\nb".to_string())),
+            (root.path().join("web"), JobState::Failed("exit code 2\nboom".to_string())),
+        ];
+        let popup = exec_popup(&results);
+        let lines = popup.lines.iter().map(|(text, _)| text.as_str()).collect::<Vec<_>>();
+        assert_eq!(lines, vec!["api: ok", "  a", "  b", "web: exit code 2", "  boom"]);
+        assert!(popup.lines[4].1 == LineStyle::Error);
+    }
+}
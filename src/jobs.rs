@@ -0,0 +1,258 @@
+use crate::prelude::*;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+const WAIT_DELAY_SECS: f32 = 0.05;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum JobState {
+    Queued,
+    Running,
+    Done(String),
+    // The job decided not to touch the repo, with the reason.
+    Skipped(String),
+    Failed(String),
+}
+
+// Returned by a job as an error when a repo is skipped.
+#[derive(Debug)]
+pub struct Skip(pub String);
+
+impl std::fmt::Display for Skip {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "skipped: {}", self.0)
+    }
+}
+
+impl std::error::Error for Skip {}
+
+pub fn skip(reason: &str) -> Box<dyn std::error::Error> {
+    Box::new(Skip(reason.to_string()))
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, Self::Done(_) | Self::Skipped(_) | Self::Failed(_))
+    }
+
+    pub fn text(&self) -> String {
+        match self {
+            Self::Queued => "queued".to_string(),
+            Self::Running => "running".to_string(),
+            Self::Done(text) => text.to_string(),
+            Self::Skipped(reason) => format!("skipped: {}", reason),
+            Self::Failed(error) => format!("error: {}", error),
+        }
+    }
+
+    pub fn line_style(&self) -> LineStyle {
+        match self {
+            Self::Queued | Self::Running => LineStyle::Dim,
+            Self::Done(_) => LineStyle::Good,
+            Self::Skipped(_) => LineStyle::Warning,
+            Self::Failed(_) => LineStyle::Error,
+        }
+    }
+
+    // Short marker shown after the repo name while the job runs.
+    pub fn marker(&self, frame: usize) -> String {
+        match self {
+            Self::Queued => " .".to_string(),
+            Self::Running => format!(" {}", SPINNER[frame % SPINNER.len()]),
+            Self::Done(_) => " ok".to_string(),
+            Self::Skipped(_) => " --".to_string(),
+            Self::Failed(_) => " !!".to_string(),
+        }
+    }
+}
+
+// Runs the same job for many repos in the background,
+// with at most `workers` repos at the same time.
+pub struct BatchJob {
+    name: String,
+    paths: Vec<PathBuf>,
+    states: Arc<Mutex<Vec<JobState>>>,
+}
+
+impl BatchJob {
+    pub fn start<F>(name: &str, paths: Vec<PathBuf>, workers: usize, job: F) -> Self
+    where
+        F: Fn(&Path) -> ReposResult<String> + Send + Sync + 'static,
+    {
+        let states = Arc::new(Mutex::new(vec![JobState::Queued; paths.len()]));
+        let next = Arc::new(AtomicUsize::new(0));
+        let job = Arc::new(job);
+        let shared_paths = Arc::new(paths.clone());
+        for _ in 0..workers.max(1).min(paths.len()) {
+            let (states, next, job, paths) = (states.clone(), next.clone(), job.clone(), shared_paths.clone());
+            std::thread::spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let path = match paths.get(index) {
+                    Some(path) => path,
+                    None => break,
+                };
+                states.lock().unwrap()[index] = JobState::Running;
+                // A panicking job would stay running, and waiting for it would never end.
+                let state = match catch_unwind(AssertUnwindSafe(|| job(path))) {
+                    Ok(Ok(text)) => JobState::Done(text),
+                    Ok(Err(error)) => match error.downcast_ref::<Skip>() {
+                        Some(Skip(reason)) => JobState::Skipped(reason.to_string()),
+                        None => JobState::Failed(error.to_string()),
+                    },
+                    Err(panic) => JobState::Failed(format!("panicked: {}", panic_message(&*panic))),
+                };
+                states.lock().unwrap()[index] = state;
+            });
+        }
+        Self {
+            name: name.to_string(),
+            paths,
+            states,
+        }
+    }
+
+    pub fn failed_count(&self) -> usize {
+        self.states
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|state| matches!(state, JobState::Failed(_)))
+            .count()
+    }
+
+    pub fn state(&self, path: &Path) -> Option<JobState> {
+        let index = self.paths.iter().position(|p| p == path)?;
+        Some(self.states.lock().unwrap()[index].clone())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.states.lock().unwrap().iter().all(|s| s.is_finished())
+    }
+
+    pub fn results(&self) -> Vec<(PathBuf, JobState)> {
+        self.paths
+            .iter()
+            .cloned()
+            .zip(self.states.lock().unwrap().iter().cloned())
+            .collect()
+    }
+
+    // Blocks until every repo is done, calling `on_finished`
+    // in the order the repos finish.
+    pub fn wait(&self, mut on_finished: impl FnMut(&Path, &JobState)) -> Vec<(PathBuf, JobState)> {
+        let mut reported = vec![false; self.paths.len()];
+        loop {
+            let results = self.results();
+            for (index, (path, state)) in results.iter().enumerate() {
+                if state.is_finished() && !reported[index] {
+                    reported[index] = true;
+                    on_finished(path, state);
+                }
+            }
+            if reported.iter().all(|r| *r) {
+                return results;
+            }
+            std::thread::sleep(Duration::from_secs_f32(WAIT_DELAY_SECS));
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        summary(&self.name, &self.results())
+    }
+
+    pub fn results_popup(&self) -> Popup {
+        results_popup(&self.name, &self.results())
+    }
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.to_string(),
+        _ => "unknown error".to_string(),
+    }
+}
+
+// Like "fetch: 3 ok, 1 skipped, 1 failed: api",
+// or "push: api: pushed dev to origin" for a single repo, with the first line of the text.
+pub fn summary(name: &str, results: &[(PathBuf, JobState)]) -> String {
+    if let [(path, state)] = results {
+        let text = state.text();
+        return format!("{}: {}: {}", name, repo_name(path), text.lines().next().unwrap_or_default());
+    }
+    let failed = results
+        .iter()
+        .filter(|(_, state)| matches!(state, JobState::Failed(_)))
+        .map(|(path, _)| repo_name(path))
+        .collect::<Vec<_>>();
+    let skipped = results
+        .iter()
+        .filter(|(_, state)| matches!(state, JobState::Skipped(_)))
+        .count();
+    let mut summary = format!("{}: {} ok", name, results.len() - failed.len() - skipped);
+    if skipped > 0 {
+        summary.push_str(&format!(", {} skipped", skipped));
+    }
+    if !failed.is_empty() {
+        summary.push_str(&format!(", {} failed: {}", failed.len(), failed.join(", ")));
+    }
+    summary
+}
+
+pub fn results_popup(name: &str, results: &[(PathBuf, JobState)]) -> Popup {
+    let lines = results
+        .iter()
+        .map(|(path, state)| (format!("{}: {}", repo_name(path), state.text()), state.line_style()))
+        .collect();
+    Popup::new(&summary(name, results), lines)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn running_jobs_with_limited_workers() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let paths = (0..10).map(|i| PathBuf::from(format!("/repos/{}", i))).collect::<Vec<_>>();
+        let (running_c, max_running_c) = (running.clone(), max_running.clone());
+        let job = BatchJob::start("test", paths, 3, move |path| {
+            let now = running_c.fetch_add(1, Ordering::SeqCst) + 1;
+            max_running_c.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(10));
+            running_c.fetch_sub(1, Ordering::SeqCst);
+            if path.ends_with("3") {
+                return Err(Box::new(Error::other("boom")));
+            }
+            if path.ends_with("5") {
+                return Err(skip("nothing to do"));
+            }
+            Ok(String::new())
+        });
+        let mut finished = 0;
+        let results = job.wait(|_, _| finished += 1);
+        assert_eq!(finished, 10);
+        assert_eq!(results[5].1, JobState::Skipped("nothing to do".to_string()));
+        assert!(max_running.load(Ordering::SeqCst) <= 3);
+        assert_eq!(results[3].1, JobState::Failed("boom".to_string()));
+        assert!(job.is_finished());
+        assert_eq!(job.failed_count(), 1);
+        assert_eq!(job.summary(), "test: 8 ok, 1 skipped, 1 failed: 3");
+
+        let job = BatchJob::start("test", vec![PathBuf::from("/repos/api")], 3, |_| Ok("done\nmore".to_string()));
+        job.wait(|_, _| {});
+        assert_eq!(job.summary(), "test: api: done");
+
+        let paths = vec![PathBuf::from("/repos/api"), PathBuf::from("/repos/web")];
+        let job = BatchJob::start("test", paths, 1, |path| match path.ends_with("api") {
+            true => panic!("corrupt index"),
+            false => Ok("done".to_string()),
+        });
+        let results = job.wait(|_, _| {});
+        assert_eq!(results[0].1, JobState::Failed("panicked: corrupt index".to_string()));
+        assert_eq!(results[1].1, JobState::Done("done".to_string()));
+    }
+}
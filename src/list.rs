@@ -0,0 +1,173 @@
+use crate::prelude::*;
+use serde_json::{json, Map, Value};
+
+const STATUS_WIDTH: usize = 23;
+
+#[derive(PartialEq, Clone, Copy)]
+pub enum ListFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+impl std::str::FromStr for ListFormat {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err(Error::other(format!(r#"Unknown format: "{}"."#, text))),
+        }
+    }
+}
+
+pub struct ListOptions {
+    pub format: ListFormat,
+    // Return an error if any of the repos is dirty.
+    pub fail_on_dirty: bool,
+}
+
+pub fn list(args: &Args, options: &ListOptions) -> ReposResult<()> {
+    let repos = collect_repos(&args.sources, &args.config, &RepoSort::Alpha)?;
+    let output = match options.format {
+        ListFormat::Table => format_table(&repos, &args.config),
+        ListFormat::Json => format_json(&repos)?,
+        ListFormat::Csv => format_csv(&repos),
+    };
+    print!("{}", output);
+    let dirty_count = repos
+        .iter()
+        .filter(|r| r.status().status_type() == StatusType::Dirty)
+        .count();
+    if options.fail_on_dirty && dirty_count > 0 {
+        return Err(Box::new(Error::other(format!("{} dirty repo(s).", dirty_count))));
+    }
+    Ok(())
+}
+
+fn format_table(repos: &[Repo], config: &Config) -> String {
+    let name_width = config.repo_name_width as usize;
+    let branch_width = config.max_branch_name_width;
+    let mut output = format!(
+        "{} {} {} {} {}\n",
+        text_to_width("NAME", &name_width),
+        text_to_width("STATUS", &STATUS_WIDTH),
+        text_to_width("BRANCH", &branch_width),
+        "PATH",
+        "BRANCHES"
+    );
+    for repo in repos {
+        output.push_str(&format!(
+            "{} {} {} {} {}\n",
+            text_to_width(&format!("{}{}", repo.name(), repo.kind().marker()), &name_width),
+            text_to_width(&repo.status().to_string(), &STATUS_WIDTH),
+            text_to_width(repo.current_branch(), &branch_width),
+            repo.path().display(),
+            repo.branches().join(",")
+        ));
+    }
+    output
+}
+
+fn format_json(repos: &[Repo]) -> ReposResult<String> {
+    let rows = repos
+        .iter()
+        .map(|repo| {
+            let status = repo
+                .status()
+                .flags()
+                .iter()
+                .map(|(name, value)| (name.to_string(), Value::Bool(*value)))
+                .collect::<Map<_, _>>();
+            json!({
+                "name": repo.name(),
+                "path": repo.path(),
+                "kind": repo.kind().name(),
+                "current_branch": repo.current_branch(),
+                "trunk": repo.trunk(),
+                "branches": repo.branches(),
+                "clean": repo.status().status_type() != StatusType::Dirty,
+                "ahead": repo.status().ahead_behind().map(|(ahead, _)| ahead),
+                "behind": repo.status().ahead_behind().map(|(_, behind)| behind),
+                "status": status,
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(format!("{}\n", serde_json::to_string_pretty(&rows)?))
+}
+
+fn format_csv(repos: &[Repo]) -> String {
+    let mut header = ["name", "path", "kind", "current_branch", "trunk", "branches", "clean", "ahead", "behind"]
+        .map(String::from)
+        .to_vec();
+    header.extend(Status::new().flags().iter().map(|(name, _)| name.to_string()));
+    let mut output = format!("{}\n", header.join(","));
+    for repo in repos {
+        let ahead_behind = repo.status().ahead_behind();
+        let mut fields = vec![
+            repo.name().to_string(),
+            repo.path().display().to_string(),
+            repo.kind().name().to_string(),
+            repo.current_branch().to_string(),
+            repo.trunk().to_string(),
+            repo.branches().join(" "),
+            (repo.status().status_type() != StatusType::Dirty).to_string(),
+            ahead_behind.map(|(ahead, _)| ahead.to_string()).unwrap_or_default(),
+            ahead_behind.map(|(_, behind)| behind.to_string()).unwrap_or_default(),
+        ];
+        fields.extend(repo.status().flags().iter().map(|(_, value)| value.to_string()));
+        let fields = fields.iter().map(|f| csv_field(f)).collect::<Vec<_>>();
+        output.push_str(&format!("{}\n", fields.join(",")));
+    }
+    output
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutils::*;
+
+    #[test]
+    fn escaping_csv_fields() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn formatting_repos() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("api");
+        let repo = init_repo(&path);
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.branch("dev", &head, false).unwrap();
+        std::fs::write(path.join("new_file"), "").unwrap();
+        let mut repo = open_repo(&path);
+        repo.sort_branches();
+        let repos = vec![repo];
+
+        let rows: Value = serde_json::from_str(&format_json(&repos).unwrap()).unwrap();
+        assert_eq!(rows[0]["name"], "api");
+        assert_eq!(rows[0]["current_branch"], "master");
+        assert_eq!(rows[0]["branches"], json!(["dev", "master"]));
+        assert_eq!(rows[0]["clean"], false);
+        assert_eq!(rows[0]["ahead"], Value::Null);
+        assert_eq!(rows[0]["status"]["wt_new"], true);
+        assert_eq!(rows[0]["status"]["wt_modified"], false);
+
+        let csv = format_csv(&repos);
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("name,path,kind,current_branch,trunk,branches,clean,ahead,behind,index_new,"));
+        assert!(lines[1].starts_with(&format!("api,{},normal,master,master,dev master,false,,,", path.display())));
+    }
+}
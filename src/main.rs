@@ -1,14 +1,30 @@
+mod config;
+mod exec;
+mod jobs;
+mod list;
+mod popup;
+mod remote;
 mod repo;
 mod repos;
 mod repostatus;
+mod switch;
+#[cfg(test)]
+mod testutils;
 mod tui;
 mod utils;
 
 mod prelude {
+    pub use crate::config::*;
+    pub use crate::exec::*;
+    pub use crate::jobs::*;
+    pub use crate::list::*;
+    pub use crate::popup::*;
+    pub use crate::remote::*;
     pub use crate::repo::*;
-    pub use crate::repos::{run, RepoSort};
+    pub use crate::repos::{collect_repos, find_repo_paths, run, RepoSort};
     pub use crate::repostatus::*;
-    pub use crate::tui::{CellStyle, Column, Direction, ToColumn, Tui};
+    pub use crate::switch::*;
+    pub use crate::tui::{CellStyle, Column, Direction, MessageLevel, Prompt, PromptEvent, ToColumn, Tui};
     pub use crate::utils::*;
     pub use clap::{Arg, Command as ClapCommand};
     pub use crossterm::{
@@ -17,30 +33,39 @@ mod prelude {
         style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
         terminal,
         terminal::{disable_raw_mode, enable_raw_mode, Clear, ClearType},
-        Command, ExecutableCommand, QueueableCommand,
+        QueueableCommand,
     };
     pub use git2::{BranchType, ErrorCode, Repository, StatusOptions};
     pub use std::{
         env::var,
-        fs,
-        io::{self, Write},
-        io::{stdout, Error, ErrorKind},
+        io::Write,
+        io::{stdout, Error},
         path::{Path, PathBuf},
-        thread::sleep,
-        time::{Duration, Instant},
+        time::Duration,
     };
     pub type ReposResult<T> = Result<T, Box<dyn std::error::Error>>;
-    pub const DEV_DIR_ENV_VAR: &str = "DEVDIR";
-    pub const UPDATE_DELAY_SECS: f32 = 0.5;
-    pub const REPO_NAME_WIDTH: u16 = 35;
-    pub const MAX_BRANCH_NAME_WIDTH: usize = 30;
-    pub const INCLUDE_IGNORED: bool = false;
 }
 
 use prelude::*;
 
 fn main() {
-    if let Err(error) = get_root_path().and_then(run) {
+    if let Err(error) = get_args().and_then(dispatch) {
         eprintln!("Error: {}", error);
+        std::process::exit(1);
     };
 }
+
+fn dispatch(args: Args) -> ReposResult<()> {
+    match args.command {
+        SubCommand::Tui => run(args),
+        SubCommand::List(ref options) => list(&args, options),
+        SubCommand::Fetch => fetch(&args),
+        SubCommand::Pull => pull(&args),
+        SubCommand::Switch(ref options) => switch(&args, options),
+        SubCommand::Exec(ref options) => exec(&args, options),
+        SubCommand::ConfigShow => {
+            print!("{}", args.config.to_toml()?);
+            Ok(())
+        }
+    }
+}
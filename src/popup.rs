@@ -0,0 +1,91 @@
+use crate::prelude::*;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LineStyle {
+    Plain,
+    Heading,
+    Dim,
+    Good,
+    Warning,
+    Error,
+}
+
+impl LineStyle {
+    pub fn color(&self) -> Color {
+        match self {
+            Self::Plain => Color::Reset,
+            Self::Heading => Color::Cyan,
+            Self::Dim => Color::Rgb { r: 95, g: 85, b: 80 },
+            Self::Good => Color::Green,
+            Self::Warning => Color::Rgb { r: 255, g: 205, b: 0 },
+            Self::Error => Color::Rgb { r: 255, g: 0, b: 0 },
+        }
+    }
+}
+
+// A scrollable list drawn over the repos, like the results of a pull.
+pub struct Popup {
+    pub title: String,
+    pub lines: Vec<(String, LineStyle)>,
+    // Keys shown in the last line.
+    pub help: String,
+    selected: usize,
+}
+
+impl Popup {
+    pub fn new(title: &str, lines: Vec<(String, LineStyle)>) -> Self {
+        Self {
+            title: title.to_string(),
+            lines,
+            help: "j/k: move  esc/q: close".to_string(),
+            selected: 0,
+        }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    // Keeps the selection when the list is opened again after a change.
+    pub fn select(&mut self, index: usize) {
+        self.selected = index.min(self.lines.len().saturating_sub(1));
+    }
+
+    pub fn go(&mut self, direction: Direction) {
+        match direction {
+            Direction::Up => self.selected = self.selected.saturating_sub(1),
+            Direction::Down if self.selected + 1 < self.lines.len() => self.selected += 1,
+            _ => {}
+        }
+    }
+
+    // First line shown, so the selected line is always visible.
+    pub fn first_visible_line(&self, height: usize) -> usize {
+        if height == 0 || self.selected < height {
+            0
+        } else {
+            self.selected + 1 - height
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scrolling_to_the_selected_line() {
+        let lines = (0..10).map(|i| (i.to_string(), LineStyle::Dim)).collect();
+        let mut popup = Popup::new("test", lines);
+        popup.go(Direction::Up);
+        assert_eq!(popup.selected(), 0);
+        for _ in 0..20 {
+            popup.go(Direction::Down);
+        }
+        assert_eq!(popup.selected(), 9);
+        assert_eq!(popup.first_visible_line(4), 6);
+        assert_eq!(popup.first_visible_line(20), 0);
+        popup.select(30);
+        assert_eq!(popup.selected(), 9);
+    }
+}
@@ -0,0 +1,317 @@
+use crate::prelude::*;
+use git2::{build::CheckoutBuilder, Cred, CredentialType, FetchOptions, PushOptions, RemoteCallbacks};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+const DEFAULT_REMOTE: &str = "origin";
+
+// libgit2 keeps asking for credentials while they are rejected.
+const MAX_CREDENTIAL_ATTEMPTS: usize = 3;
+
+// Credentials from the ssh agent or the git credential helpers.
+pub fn remote_callbacks<'a>(config: git2::Config) -> RemoteCallbacks<'a> {
+    let attempts = Cell::new(0);
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |url, username, allowed| {
+        attempts.set(attempts.get() + 1);
+        if attempts.get() > MAX_CREDENTIAL_ATTEMPTS {
+            return Err(git2::Error::from_str("Authentication failed."));
+        }
+        if allowed.contains(CredentialType::SSH_KEY) {
+            Cred::ssh_key_from_agent(username.unwrap_or("git"))
+        } else if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            Cred::credential_helper(&config, url, username)
+        } else {
+            Cred::default()
+        }
+    });
+    callbacks
+}
+
+pub fn fetch_repo(path: &Path) -> ReposResult<String> {
+    let repo = Repository::open(path)?;
+    let remotes = repo.remotes()?;
+    if remotes.is_empty() {
+        return Ok("no remotes".to_string());
+    }
+    for name in remotes.iter().flatten() {
+        let mut remote = repo.find_remote(name)?;
+        let mut options = FetchOptions::new();
+        options.remote_callbacks(remote_callbacks(repo.config()?));
+        // Empty refspecs: the ones configured for the remote.
+        remote
+            .fetch(&[] as &[&str], Some(&mut options), None)
+            .map_err(|error| Error::other(format!("{}: {}", name, error.message())))?;
+    }
+    Ok(format!("{} remote(s)", remotes.len()))
+}
+
+// Fast-forwards the current branch to its upstream.
+// Only clean repos are pulled, the rest is skipped with the reason.
+pub fn pull_repo(path: &Path, settings: &RepoSettings) -> ReposResult<String> {
+    let repo = Repository::open(path)?;
+    if repo.is_bare() {
+        return Err(skip("bare repo"));
+    }
+    if read_status(&repo, settings)?.status_type() == StatusType::Dirty {
+        return Err(skip("dirty"));
+    }
+    let head = repo.head().map_err(|_| skip("no commits"))?;
+    if !head.is_branch() {
+        return Err(skip("detached HEAD"));
+    }
+    let branch = repo.find_branch(head.shorthand().unwrap_or_default(), BranchType::Local)?;
+    let upstream = branch.upstream().map_err(|_| skip("no upstream"))?;
+    let local_oid = head.target().ok_or_else(|| skip("no commits"))?;
+    let upstream_oid = upstream.get().target().ok_or_else(|| skip("no upstream"))?;
+    let (ahead, behind) = repo.graph_ahead_behind(local_oid, upstream_oid)?;
+    if behind == 0 {
+        return Ok("up to date".to_string());
+    }
+    if ahead > 0 {
+        return Err(skip(&format!("diverged{}", format_ahead_behind(Some((ahead, behind))))));
+    }
+    let target = repo.find_commit(upstream_oid)?;
+    // The files are updated first, so the branch is not moved when that fails.
+    repo.checkout_tree(target.as_object(), Some(CheckoutBuilder::new().safe()))?;
+    repo.find_reference(head.name().unwrap_or_default())?
+        .set_target(upstream_oid, "repos: fast-forward")?;
+    Ok(format!("fast-forwarded {} commit(s)", behind))
+}
+
+// Pushes the current branch to the remote of its upstream, or to "origin".
+// Without an upstream the pushed branch is set as the upstream.
+// The trunk is only pushed when forced.
+pub fn push_repo(path: &Path, settings: &RepoSettings, force_trunk: bool) -> ReposResult<String> {
+    let repo = Repository::open(path)?;
+    if repo.is_bare() {
+        return Err(skip("bare repo"));
+    }
+    let head = repo.head().map_err(|_| skip("no commits"))?;
+    if !head.is_branch() {
+        return Err(skip("detached HEAD"));
+    }
+    let branch_name = head.shorthand().unwrap_or_default().to_string();
+    let ref_name = head.name().unwrap_or_default().to_string();
+    if !force_trunk && branch_name == read_trunk(&repo, settings) {
+        return Err(skip(&format!(r#"refusing to push the trunk "{}""#, branch_name)));
+    }
+    let has_upstream = repo.branch_upstream_name(&ref_name).is_ok();
+    let remote_name = match repo.branch_upstream_remote(&ref_name) {
+        Ok(name) => name.as_str().unwrap_or(DEFAULT_REMOTE).to_string(),
+        Err(_) => {
+            let remotes = repo.remotes()?;
+            match remotes.iter().flatten().find(|r| *r == DEFAULT_REMOTE).or_else(|| remotes.get(0)) {
+                Some(name) => name.to_string(),
+                None => return Err(skip("no remote")),
+            }
+        }
+    };
+    // Like push.default=simple, the branch is only pushed to the same name.
+    // Pushing to a differently named upstream could update the remote trunk.
+    if let Ok(merge) = repo.config()?.get_string(&format!("branch.{}.merge", branch_name)) {
+        if merge != ref_name {
+            let upstream = merge.strip_prefix("refs/heads/").unwrap_or(&merge);
+            return Err(skip(&format!(r#"the upstream "{}" has another name than "{}""#, upstream, branch_name)));
+        }
+    }
+
+    let mut remote = repo.find_remote(&remote_name)?;
+    let rejected = Rc::new(RefCell::new(None));
+    let mut callbacks = remote_callbacks(repo.config()?);
+    let rejected_c = rejected.clone();
+    callbacks.push_update_reference(move |_, status| {
+        if let Some(message) = status {
+            *rejected_c.borrow_mut() = Some(message.to_string());
+        }
+        Ok(())
+    });
+    let mut options = PushOptions::new();
+    options.remote_callbacks(callbacks);
+    remote
+        .push(&[format!("{0}:{0}", ref_name)], Some(&mut options))
+        .map_err(|error| Error::other(format!("{}: {}", remote_name, error.message())))?;
+    if let Some(message) = rejected.borrow_mut().take() {
+        return Err(Box::new(Error::other(format!("{}: rejected: {}", remote_name, message))));
+    }
+    if !has_upstream {
+        repo.find_branch(&branch_name, BranchType::Local)?
+            .set_upstream(Some(&format!("{}/{}", remote_name, branch_name)))?;
+        return Ok(format!("pushed {} to {}, upstream set", branch_name, remote_name));
+    }
+    Ok(format!("pushed {} to {}", branch_name, remote_name))
+}
+
+pub fn pull(args: &Args) -> ReposResult<()> {
+    let paths = find_repo_paths(&args.sources)?;
+    let config = args.config.clone();
+    let job = BatchJob::start("pull", paths, args.config.workers, move |path| {
+        pull_repo(path, &config.repo_settings(path))
+    });
+    job.wait(print_job_state);
+    println!("{}", job.summary());
+    fail_on_job_errors(&job)
+}
+
+pub fn fetch(args: &Args) -> ReposResult<()> {
+    let paths = find_repo_paths(&args.sources)?;
+    let job = BatchJob::start("fetch", paths, args.config.workers, fetch_repo);
+    job.wait(print_job_state);
+    println!("{}", job.summary());
+    fail_on_job_errors(&job)
+}
+
+pub fn print_job_state(path: &Path, state: &JobState) {
+    match state {
+        JobState::Failed(_) => eprintln!("{}: {}", repo_name(path), state.text()),
+        _ => println!("{}: {}", repo_name(path), state.text()),
+    }
+}
+
+pub fn fail_on_job_errors(job: &BatchJob) -> ReposResult<()> {
+    let failed = job.failed_count();
+    if failed > 0 {
+        return Err(Box::new(Error::other(format!("{} failed.", failed))));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutils::*;
+
+    #[test]
+    fn fetching_from_a_local_bare_remote() {
+        let root = tempfile::tempdir().unwrap();
+        let (remote, seed) = init_remote(root.path());
+        let clone = clone_repo(&remote, &root.path().join("clone"));
+        commit_file(&seed, "new", "new", "New on remote.");
+        push(&seed, "master");
+        assert_eq!(open_repo(&root.path().join("clone")).status().ahead_behind(), Some((0, 0)));
+
+        assert_eq!(fetch_repo(clone.workdir().unwrap()).unwrap(), "1 remote(s)");
+        assert_eq!(open_repo(&root.path().join("clone")).status().ahead_behind(), Some((0, 1)));
+    }
+
+    fn pull(path: &Path) -> ReposResult<String> {
+        pull_repo(path, &Config::default().repo_settings(path))
+    }
+
+    fn assert_skipped(result: ReposResult<String>, reason: &str) {
+        let error = result.unwrap_err();
+        assert_eq!(error.downcast_ref::<Skip>().unwrap().0, reason);
+    }
+
+    #[test]
+    fn pulling_fast_forwards_clean_repos() {
+        let root = tempfile::tempdir().unwrap();
+        let (remote, seed) = init_remote(root.path());
+        let path = root.path().join("clone");
+        clone_repo(&remote, &path);
+        assert_eq!(pull(&path).unwrap(), "up to date");
+
+        commit_file(&seed, "new", "new", "New on remote.");
+        push(&seed, "master");
+        fetch_repo(&path).unwrap();
+        assert_eq!(pull(&path).unwrap(), "fast-forwarded 1 commit(s)");
+        assert!(path.join("new").is_file());
+        assert_eq!(open_repo(&path).status().status_type(), StatusType::Clean);
+    }
+
+    #[test]
+    fn pulling_skips_dirty_diverged_and_untracked_repos() {
+        let root = tempfile::tempdir().unwrap();
+        let (remote, seed) = init_remote(root.path());
+        let path = root.path().join("clone");
+        let clone = clone_repo(&remote, &path);
+        commit_file(&seed, "new", "new", "New on remote.");
+        push(&seed, "master");
+        fetch_repo(&path).unwrap();
+
+        std::fs::write(path.join("README"), "changed").unwrap();
+        assert_skipped(pull(&path), "dirty");
+
+        commit_file(&clone, "README", "changed", "Local change.");
+        assert_skipped(pull(&path), "diverged +1-1");
+
+        let head = clone.head().unwrap().peel_to_commit().unwrap();
+        clone.branch("local-only", &head, false).unwrap();
+        clone.set_head("refs/heads/local-only").unwrap();
+        assert_skipped(pull(&path), "no upstream");
+    }
+
+    #[test]
+    fn pushing_a_new_branch_sets_the_upstream() {
+        let root = tempfile::tempdir().unwrap();
+        let (remote, _) = init_remote(root.path());
+        let path = root.path().join("clone");
+        let clone = clone_repo(&remote, &path);
+        let head = clone.head().unwrap().peel_to_commit().unwrap();
+        clone.branch("feature", &head, false).unwrap();
+        clone.set_head("refs/heads/feature").unwrap();
+        commit_file(&clone, "feature", "feature", "Feature.");
+
+        let settings = Config::default().repo_settings(&path);
+        assert_eq!(push_repo(&path, &settings, false).unwrap(), "pushed feature to origin, upstream set");
+        assert_eq!(open_repo(&path).status().ahead_behind(), Some((0, 0)));
+        let remote_repo = Repository::open_bare(&remote).unwrap();
+        assert!(remote_repo.find_branch("feature", BranchType::Local).is_ok());
+
+        commit_file(&clone, "feature", "more", "More feature.");
+        assert_eq!(push_repo(&path, &settings, false).unwrap(), "pushed feature to origin");
+    }
+
+    #[test]
+    fn pushing_the_trunk_only_when_forced() {
+        let root = tempfile::tempdir().unwrap();
+        let (remote, seed) = init_remote(root.path());
+        let path = root.path().join("clone");
+        let clone = clone_repo(&remote, &path);
+        commit_file(&clone, "a", "a", "Local.");
+        let settings = Config::default().repo_settings(&path);
+        assert_skipped(push_repo(&path, &settings, false), r#"refusing to push the trunk "master""#);
+        assert_eq!(push_repo(&path, &settings, true).unwrap(), "pushed master to origin");
+
+        // Not a fast-forward on the remote.
+        commit_file(&seed, "b", "b", "Seed.");
+        let error = push_repo(&root.path().join("seed"), &settings, true).unwrap_err();
+        assert!(error.to_string().starts_with("origin: "));
+    }
+
+    #[test]
+    fn not_pushing_to_an_upstream_with_another_name() {
+        let root = tempfile::tempdir().unwrap();
+        let (remote, _) = init_remote(root.path());
+        let path = root.path().join("clone");
+        let clone = clone_repo(&remote, &path);
+        let head = clone.head().unwrap().peel_to_commit().unwrap();
+        clone
+            .branch("fix", &head, false)
+            .unwrap()
+            .set_upstream(Some("origin/master"))
+            .unwrap();
+        clone.set_head("refs/heads/fix").unwrap();
+        commit_file(&clone, "a", "a", "Fix.");
+
+        let settings = Config::default().repo_settings(&path);
+        for force_trunk in [false, true] {
+            assert_skipped(
+                push_repo(&path, &settings, force_trunk),
+                r#"the upstream "master" has another name than "fix""#,
+            );
+        }
+        let remote_master = Repository::open(&remote).unwrap().refname_to_id("refs/heads/master").unwrap();
+        assert_eq!(remote_master, head.id());
+    }
+
+    #[test]
+    fn fetching_fails_with_a_missing_remote() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = init_repo(&root.path().join("repo"));
+        assert_eq!(fetch_repo(&root.path().join("repo")).unwrap(), "no remotes");
+        repo.remote("origin", root.path().join("missing.git").to_str().unwrap()).unwrap();
+        let error = fetch_repo(&root.path().join("repo")).unwrap_err();
+        assert!(error.to_string().starts_with("origin: "));
+    }
+}
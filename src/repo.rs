@@ -1,40 +1,227 @@
 use crate::prelude::*;
+use git2::build::CheckoutBuilder;
+use git2::{ApplyLocation, ApplyOptions, CheckoutNotificationType, Diff, DiffOptions, Patch};
+use std::collections::HashMap;
 
-const NO_BRANCH: &str = "(no branch)";
+pub const NO_BRANCH: &str = "(no branch)";
+const ORIGIN_HEAD: &str = "refs/remotes/origin/HEAD";
+const ORIGIN_PREFIX: &str = "refs/remotes/origin/";
+// Used when there's no origin/HEAD and no trunk in the config,
+// the first existing local branch is the trunk.
+const TRUNK_FALLBACKS: [&str; 2] = ["main", "master"];
+// Message of the stashes made when leaving a branch, followed by the branch name.
+const AUTO_STASH_PREFIX: &str = "repos-autostash:";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RepoKind {
+    Normal,
+    // Linked worktree, created by "git worktree add".
+    Worktree,
+    // ".git" is a file pointing to the git dir somewhere else, like in submodules.
+    GitFile,
+    Bare,
+}
+
+impl RepoKind {
+    fn detect(repo: &Repository, path: &Path) -> Self {
+        if repo.is_bare() {
+            Self::Bare
+        } else if repo.is_worktree() {
+            Self::Worktree
+        } else if path.join(".git").is_file() {
+            Self::GitFile
+        } else {
+            Self::Normal
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Normal => "normal",
+            Self::Worktree => "worktree",
+            Self::GitFile => "gitfile",
+            Self::Bare => "bare",
+        }
+    }
+
+    pub fn marker(&self) -> &str {
+        match self {
+            Self::Normal | Self::GitFile => "",
+            Self::Worktree => " [wt]",
+            Self::Bare => " [bare]",
+        }
+    }
+
+    pub fn can_checkout(&self) -> bool {
+        *self != Self::Bare
+    }
+}
+
+// A line of the log pane.
+pub struct LogEntry {
+    pub id: String,
+    pub author: String,
+    // Seconds since the epoch.
+    pub time: i64,
+    pub summary: String,
+}
+
+// A hunk of the diff of a file, the lines start with "+", "-" or " ".
+#[derive(Debug)]
+pub struct DiffHunk {
+    // Index against HEAD, or worktree against index.
+    pub staged: bool,
+    pub header: String,
+    pub lines: Vec<String>,
+}
+
+// A changed file, with the states like in "git status --short".
+#[derive(PartialEq, Debug)]
+pub struct FileStatus {
+    pub path: String,
+    pub index: char,
+    pub worktree: char,
+}
+
+impl FileStatus {
+    fn new(path: &str, status: git2::Status) -> Self {
+        let (index, worktree) = if status.is_conflicted() {
+            ('U', 'U')
+        } else if status.is_wt_new() {
+            ('?', '?')
+        } else if status.is_ignored() {
+            ('!', '!')
+        } else {
+            let index = match status {
+                s if s.is_index_new() => 'A',
+                s if s.is_index_modified() => 'M',
+                s if s.is_index_deleted() => 'D',
+                s if s.is_index_renamed() => 'R',
+                s if s.is_index_typechange() => 'T',
+                _ => ' ',
+            };
+            let worktree = match status {
+                s if s.is_wt_modified() => 'M',
+                s if s.is_wt_deleted() => 'D',
+                s if s.is_wt_renamed() => 'R',
+                s if s.is_wt_typechange() => 'T',
+                _ => ' ',
+            };
+            (index, worktree)
+        };
+        Self {
+            path: path.to_string(),
+            index,
+            worktree,
+        }
+    }
+
+    pub fn is_staged(&self) -> bool {
+        !matches!(self.index, ' ' | '?' | '!' | 'U')
+    }
+
+    pub fn is_unstaged(&self) -> bool {
+        self.worktree != ' '
+    }
+}
+
+#[derive(Debug)]
+pub enum CheckoutError {
+    BareRepo,
+    MissingBranch(String),
+    // There's no branch to checkout when HEAD is detached.
+    DetachedHead,
+    // Files with local changes that the checkout would overwrite.
+    Conflict(Vec<String>),
+    // The branch is the HEAD of another worktree, with its path.
+    CheckedOutElsewhere(String, PathBuf),
+    Git(git2::Error),
+}
+
+impl std::fmt::Display for CheckoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BareRepo => write!(f, "Can't checkout in a bare repo."),
+            Self::MissingBranch(branch) => write!(f, "No branch {}.", branch),
+            Self::DetachedHead => write!(f, "HEAD is detached, select a branch to checkout."),
+            Self::Conflict(files) => write!(f, "Local changes would be overwritten: {}", files.join(", ")),
+            Self::CheckedOutElsewhere(branch, path) => {
+                write!(f, "{} is checked out in the worktree {}.", branch, path.display())
+            }
+            Self::Git(error) => write!(f, "{}", error.message()),
+        }
+    }
+}
+
+impl std::error::Error for CheckoutError {}
+
+impl From<git2::Error> for CheckoutError {
+    fn from(error: git2::Error) -> Self {
+        Self::Git(error)
+    }
+}
 
 pub struct Repo {
     pub git_repo: Repository,
+    path: PathBuf,
+    kind: RepoKind,
+    // Index of the source the repo was found in.
+    group: usize,
     name: String,
     current_branch: String,
+    trunk: String,
     branches: Vec<String>,
+    // Like "origin/feature", without the ones that have a local branch with the same name.
+    remote_branches: Vec<String>,
+    // Only filled for the branches with an upstream, when enabled in the config.
+    branches_ahead_behind: HashMap<String, (usize, usize)>,
     status: Status,
+    stash_count: usize,
+    settings: RepoSettings,
 }
 
 impl Repo {
-    pub fn new(path: &PathBuf) -> ReposResult<Self> {
-        let repo = Repository::open(path)?;
-        let status = read_status(&repo);
+    pub fn new(path: &Path, settings: &RepoSettings) -> ReposResult<Self> {
+        let mut repo = Repository::open(path)?;
+        let kind = RepoKind::detect(&repo, path);
+        let stash_count = read_stashes(&mut repo)?.len();
+        let status = if kind == RepoKind::Bare {
+            Status::new()
+        } else {
+            read_status(&repo, settings)?
+        };
         let current_branch = read_current_branch(&repo);
+        let status = status.set_ahead_behind(read_ahead_behind(&repo, &current_branch));
+        let trunk = read_trunk(&repo, settings);
         let mut branches = read_branches(&repo);
         if branches.is_empty() {
             branches = vec![current_branch.clone()];
         }
-        let name = repo
-            .path()
-            .components()
-            .nth_back(1)
-            .map(|f| f.as_os_str())
-            .unwrap()
-            .to_owned()
-            .into_string()
-            .unwrap();
+        let remote_branches = read_remote_branches(&repo, &branches);
+        let branches_ahead_behind = if settings.ahead_behind_all_branches {
+            branches
+                .iter()
+                .filter_map(|b| read_ahead_behind(&repo, b).map(|ab| (b.to_string(), ab)))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        let name = repo_name(path);
 
         Ok(Self {
             git_repo: repo,
+            path: path.to_owned(),
+            kind,
+            group: 0,
             name,
             current_branch,
+            trunk,
             branches,
+            remote_branches,
+            branches_ahead_behind,
             status,
+            stash_count,
+            settings: settings.clone(),
         })
     }
 
@@ -42,18 +229,41 @@ impl Repo {
         self.name.as_str()
     }
 
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    pub fn kind(&self) -> RepoKind {
+        self.kind
+    }
+
+    // Reads the repo again, after changing it.
+    pub fn reload(&self) -> ReposResult<Self> {
+        Ok(Self::new(&self.path, &self.settings)?.with_group(self.group))
+    }
+
+    pub fn with_group(mut self, group: usize) -> Self {
+        self.group = group;
+        self
+    }
+
+    pub fn group(&self) -> usize {
+        self.group
+    }
+
     pub fn current_branch(&self) -> &str {
         self.current_branch.as_str()
     }
 
     pub fn sort_branches(&mut self) {
         self.branches.sort();
+        self.remote_branches.sort();
     }
 
     pub fn set_current_branch_as_first(&mut self) {
         let mut branches = vec![self.current_branch.to_string()];
         if !self.branches().is_empty() {
-            branches.extend(self.branches.iter().cloned().filter(|b| b != &self.current_branch))
+            branches.extend(self.branches.iter().filter(|b| *b != &self.current_branch).cloned())
         }
         self.branches = branches;
     }
@@ -62,25 +272,405 @@ impl Repo {
         &self.branches
     }
 
+    pub fn remote_branches(&self) -> &Vec<String> {
+        &self.remote_branches
+    }
+
+    pub fn branch_ahead_behind(&self, branch: &str) -> Option<(usize, usize)> {
+        self.branches_ahead_behind.get(branch).copied()
+    }
+
     pub fn status(&self) -> &Status {
         &self.status
     }
 
-    pub fn is_on_master(&self) -> bool {
-        self.current_branch == "master"
+    pub fn stash_count(&self) -> usize {
+        self.stash_count
+    }
+
+    pub fn trunk(&self) -> &str {
+        self.trunk.as_str()
+    }
+
+    pub fn is_on_trunk(&self) -> bool {
+        self.current_branch == self.trunk
+    }
+
+    // The current branch is "HEAD" then, it's not a branch.
+    pub fn is_detached(&self) -> bool {
+        self.git_repo.head_detached().unwrap_or(false)
     }
 
-    pub fn checkout_branch(&self, branch: String) -> ReposResult<()> {
-        if branch != NO_BRANCH {
-            std::process::Command::new("git")
-                .arg("checkout")
-                .arg(branch)
-                .current_dir(&self.git_repo.path().parent().unwrap())
-                .output()
-                .expect("Could not checkout repos.");
+    // Creates a local branch from the start point branch, or from HEAD.
+    pub fn create_branch(&self, name: &str, start_point: Option<&str>) -> ReposResult<()> {
+        let commit = match start_point {
+            Some(branch) => self.git_repo.find_branch(branch, BranchType::Local)?.get().peel_to_commit()?,
+            None => self.git_repo.head()?.peel_to_commit()?,
         };
+        self.git_repo.branch(name, &commit, false)?;
+        Ok(())
+    }
+
+    // The upstream config of the branch is moved with it by git.
+    // Like the other changes, it's shown when the repos are read again.
+    pub fn rename_branch(&self, branch: &str, new_name: &str) -> ReposResult<()> {
+        self.git_repo.find_branch(branch, BranchType::Local)?.rename(new_name, false)?;
+        Ok(())
+    }
+
+    // Creates a local branch tracking the remote one and checks it out.
+    // Returns the name of the local branch.
+    pub fn checkout_remote_branch(&self, remote_branch: &str) -> ReposResult<String> {
+        let name = self.create_tracking_branch(remote_branch)?;
+        if let Err(error) = self.checkout_branch(&name) {
+            self.delete_branch(&name, true)?;
+            return Err(Box::new(error));
+        }
+        Ok(name)
+    }
+
+    // Creates a local branch with the name of the remote one, tracking it.
+    pub fn create_tracking_branch(&self, remote_branch: &str) -> ReposResult<String> {
+        let commit = self
+            .git_repo
+            .find_branch(remote_branch, BranchType::Remote)?
+            .get()
+            .peel_to_commit()?;
+        let name = local_branch_name(remote_branch);
+        let mut branch = self.git_repo.branch(name, &commit, false)?;
+        branch.set_upstream(Some(remote_branch))?;
+        Ok(name.to_string())
+    }
+
+    // Every changed file, untracked dirs are listed file by file.
+    pub fn file_statuses(&self) -> ReposResult<Vec<FileStatus>> {
+        let mut options = StatusOptions::new();
+        options
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .include_ignored(self.settings.include_ignored);
+        let statuses = self.git_repo.statuses(Some(&mut options))?;
+        Ok(statuses
+            .iter()
+            .map(|entry| FileStatus::new(entry.path().unwrap_or_default(), entry.status()))
+            .collect())
+    }
+
+    // Deleted files are removed from the index.
+    pub fn stage_file(&self, path: &str) -> ReposResult<()> {
+        let mut index = self.git_repo.index()?;
+        if self.path.join(path).exists() {
+            index.add_path(Path::new(path))?;
+        } else {
+            index.remove_path(Path::new(path))?;
+        }
+        index.write()?;
+        Ok(())
+    }
+
+    // The index entry is reset to HEAD, or removed without commits.
+    pub fn unstage_file(&self, path: &str) -> ReposResult<()> {
+        match self.git_repo.head().and_then(|head| head.peel_to_commit()) {
+            Ok(head) => self.git_repo.reset_default(Some(head.as_object()), [path])?,
+            Err(_) => {
+                let mut index = self.git_repo.index()?;
+                index.remove_path(Path::new(path))?;
+                index.write()?;
+            }
+        }
+        Ok(())
+    }
+
+    // The staged hunks of the file, then the unstaged ones.
+    pub fn file_diff(&self, path: &str) -> ReposResult<Vec<DiffHunk>> {
+        let head = self.git_repo.head().and_then(|head| head.peel_to_tree()).ok();
+        let staged = self
+            .git_repo
+            .diff_tree_to_index(head.as_ref(), None, Some(&mut diff_options(path)))?;
+        let unstaged = self.git_repo.diff_index_to_workdir(None, Some(&mut diff_options(path)))?;
+        let mut hunks = read_hunks(&staged, true)?;
+        hunks.extend(read_hunks(&unstaged, false)?);
+        Ok(hunks)
+    }
+
+    // Stages one hunk of the unstaged ones, counted like in `file_diff`.
+    // An untracked file can't be patched in the index, its only hunk is the whole file.
+    pub fn stage_hunk(&self, path: &str, hunk: usize) -> ReposResult<()> {
+        if self.git_repo.index()?.get_path(Path::new(path), 0).is_none() {
+            return self.stage_file(path);
+        }
+        let diff = self.git_repo.diff_index_to_workdir(None, Some(&mut diff_options(path)))?;
+        let mut next = 0;
+        let mut options = ApplyOptions::new();
+        options.hunk_callback(|_| {
+            next += 1;
+            next - 1 == hunk
+        });
+        self.git_repo.apply(&diff, ApplyLocation::Index, Some(&mut options))?;
+        Ok(())
+    }
+
+    // Commits the index on HEAD, with the user from the git config.
+    // Returns the short id of the commit.
+    pub fn commit(&self, message: &str) -> ReposResult<String> {
+        if !self.kind().can_checkout() {
+            return Err(Box::new(Error::other("Can't commit in a bare repo.")));
+        }
+        let signature = self
+            .git_repo
+            .signature()
+            .map_err(|_| Error::other("No user to commit as, set user.name and user.email in the git config."))?;
+        let tree = self.git_repo.find_tree(self.git_repo.index()?.write_tree()?)?;
+        let parent = self.git_repo.head().and_then(|head| head.peel_to_commit()).ok();
+        if parent.as_ref().map(|p| p.tree_id()) == Some(tree.id()) {
+            return Err(Box::new(Error::other("Nothing staged to commit.")));
+        }
+        let parents = parent.iter().collect::<Vec<_>>();
+        let oid = self
+            .git_repo
+            .commit(Some("HEAD"), &signature, &signature, message, &tree, &parents)?;
+        Ok(short_id(oid))
+    }
+
+    // Stashing needs a mutable repository, so a new one is opened for it.
+    fn open_for_stash(&self) -> ReposResult<Repository> {
+        Ok(Repository::open(&self.path)?)
+    }
+
+    // Like "On master: wip", the newest first.
+    pub fn stashes(&self) -> ReposResult<Vec<String>> {
+        read_stashes(&mut self.open_for_stash()?)
+    }
+
+    // Untracked files are stashed too.
+    pub fn stash(&self, message: &str) -> ReposResult<()> {
+        let mut repo = self.open_for_stash()?;
+        // Stashes are local, they don't need a configured user.
+        let signature = repo.signature().or_else(|_| git2::Signature::now("repos", "repos@localhost"))?;
+        repo.stash_save(&signature, message, Some(git2::StashFlags::INCLUDE_UNTRACKED))?;
+        Ok(())
+    }
+
+    // The stash is dropped after it's applied when popped, unless it conflicts.
+    // git2 applies a conflicting stash with conflict markers, and pop would drop it.
+    pub fn apply_stash(&self, index: usize, pop: bool) -> ReposResult<()> {
+        let mut repo = self.open_for_stash()?;
+        repo.stash_apply(index, None).map_err(|error| match error.code() {
+            ErrorCode::Conflict => Box::new(Error::other("Local changes would be overwritten by the stash.")),
+            _ => Box::<dyn std::error::Error>::from(error),
+        })?;
+        if repo.index()?.has_conflicts() {
+            return Err(Box::new(Error::other("The stash conflicts, resolve the conflicts. The stash is kept.")));
+        }
+        if pop {
+            repo.stash_drop(index)?;
+        }
+        Ok(())
+    }
+
+    pub fn drop_stash(&self, index: usize) -> ReposResult<()> {
+        self.open_for_stash()?.stash_drop(index)?;
+        Ok(())
+    }
+
+    // Stashes the local changes first, they are put back when the checkout fails.
+    pub fn stash_and_checkout(&self, branch: &str) -> ReposResult<()> {
+        self.stash(&format!("repos: before checkout of {}", branch))?;
+        if let Err(error) = self.checkout_branch(branch) {
+            self.apply_stash(0, true)?;
+            return Err(Box::new(error));
+        }
+        Ok(())
+    }
+
+    // Ignored files make the status dirty with include_ignored, but they are not stashed.
+    fn has_stashable_changes(&self) -> ReposResult<bool> {
+        let mut options = StatusOptions::new();
+        options.include_untracked(true).include_ignored(false);
+        Ok(!self.git_repo.statuses(Some(&mut options))?.is_empty())
+    }
+
+    // The local changes are stashed when leaving a branch, and the stash of
+    // the checked out branch is applied. Returns what was done with the stashes.
+    pub fn checkout_with_auto_stash(&self, branch: &str) -> ReposResult<String> {
+        let previous = self.current_branch.as_str();
+        let mut notes = vec![];
+        let stashed = previous != NO_BRANCH && self.has_stashable_changes()?;
+        if stashed {
+            self.stash(&format!("{}{}", AUTO_STASH_PREFIX, previous))?;
+            notes.push(format!("stashed the changes of {}", previous));
+        }
+        if let Err(error) = self.checkout_branch(branch) {
+            if stashed {
+                self.apply_stash(0, true)?;
+            }
+            return Err(Box::new(error));
+        }
+        let message = format!("{}{}", AUTO_STASH_PREFIX, branch);
+        // Stash messages are like "On master: <message>".
+        let index = self
+            .stashes()?
+            .iter()
+            .position(|stash| stash.split_once(": ").map(|(_, m)| m) == Some(message.as_str()));
+        if let Some(index) = index {
+            // A failed pop keeps the stash.
+            match self.apply_stash(index, true) {
+                Ok(()) => notes.push(format!("applied the changes of {}", branch)),
+                Err(error) => notes.push(format!("applying stash@{{{}}} of {} failed: {}", index, branch, error)),
+            }
+        }
+        Ok(notes.join(", "))
+    }
+
+    // Commits of the reference, like "refs/heads/dev" or "HEAD", the newest first.
+    pub fn log(&self, reference: &str, skip: usize, count: usize) -> ReposResult<Vec<LogEntry>> {
+        let target = self.git_repo.revparse_single(reference)?.peel_to_commit()?.id();
+        let mut revwalk = self.git_repo.revwalk()?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+        revwalk.push(target)?;
+        revwalk
+            .skip(skip)
+            .take(count)
+            .map(|oid| {
+                let commit = self.git_repo.find_commit(oid?)?;
+                let author = commit.author().name().unwrap_or_default().to_string();
+                Ok(LogEntry {
+                    id: short_id(commit.id()),
+                    author,
+                    time: commit.time().seconds(),
+                    summary: commit.summary().unwrap_or_default().to_string(),
+                })
+            })
+            .collect()
+    }
+
+    // Commits that are only on this branch and are lost when it's deleted,
+    // like "1a2b3c4 Fix login", the newest first.
+    pub fn unmerged_commits(&self, branch: &str) -> ReposResult<Vec<String>> {
+        let target = self.git_repo.find_branch(branch, BranchType::Local)?.get().peel_to_commit()?.id();
+        let mut revwalk = self.git_repo.revwalk()?;
+        revwalk.push(target)?;
+        for other in self.git_repo.branches(None)? {
+            let (other, branch_type) = other?;
+            if branch_type == BranchType::Local && other.name()? == Some(branch) {
+                continue;
+            }
+            if let Some(oid) = other.get().target() {
+                revwalk.hide(oid)?;
+            }
+        }
+        revwalk
+            .map(|oid| {
+                let commit = self.git_repo.find_commit(oid?)?;
+                Ok(format!("{} {}", short_id(commit.id()), commit.summary().unwrap_or_default()))
+            })
+            .collect()
+    }
+
+    // Unmerged branches are only deleted when forced.
+    pub fn delete_branch(&self, branch: &str, force: bool) -> ReposResult<()> {
+        if branch == self.current_branch {
+            return Err(Box::new(Error::other("Can't delete the current branch.")));
+        }
+        if !force && !self.unmerged_commits(branch)?.is_empty() {
+            return Err(Box::new(Error::other(format!("{} has unmerged commits.", branch))));
+        }
+        self.git_repo.find_branch(branch, BranchType::Local)?.delete()?;
         Ok(())
     }
+
+    // Safe checkout, local changes are never overwritten.
+    pub fn checkout_branch(&self, branch: &str) -> Result<(), CheckoutError> {
+        if !self.kind.can_checkout() {
+            return Err(CheckoutError::BareRepo);
+        }
+        if branch == NO_BRANCH {
+            return Err(CheckoutError::DetachedHead);
+        }
+        let reference = self
+            .git_repo
+            .find_branch(branch, BranchType::Local)
+            .map_err(|_| CheckoutError::MissingBranch(branch.to_string()))?
+            .into_reference();
+        let reference_name = reference.name().unwrap_or_default().to_string();
+        if let Some(path) = self.worktree_with_head(&reference_name) {
+            return Err(CheckoutError::CheckedOutElsewhere(branch.to_string(), path));
+        }
+        let tree = reference.peel_to_tree()?;
+
+        // The tree is checked out before moving HEAD, a conflict changes nothing.
+        // Moving HEAD first would make the new tree the baseline of a safe checkout,
+        // and the files would be left as they are.
+        let mut conflicts = vec![];
+        let mut options = CheckoutBuilder::new();
+        options.safe().notify_on(CheckoutNotificationType::CONFLICT).notify(|_, path, _, _, _| {
+            conflicts.extend(path.map(|p| p.display().to_string()));
+            true
+        });
+        let result = self.git_repo.checkout_tree(tree.as_object(), Some(&mut options));
+        drop(options);
+        match result {
+            Err(error) if error.code() == ErrorCode::Conflict => return Err(CheckoutError::Conflict(conflicts)),
+            Err(error) => return Err(error.into()),
+            Ok(()) => {}
+        }
+        if let Err(error) = self.git_repo.set_head(&reference_name) {
+            self.restore_head_files(&tree)?;
+            return Err(error.into());
+        }
+        Ok(())
+    }
+
+    // Puts back the files of HEAD that a checkout of the tree changed, when HEAD couldn't be moved.
+    // Only the files that differ between the trees are touched, the safe checkout kept the others.
+    fn restore_head_files(&self, checked_out: &git2::Tree) -> Result<(), git2::Error> {
+        let head = self.git_repo.head().and_then(|head| head.peel_to_tree()).ok();
+        let diff = self.git_repo.diff_tree_to_tree(head.as_ref(), Some(checked_out), None)?;
+        let paths = diff
+            .deltas()
+            .flat_map(|delta| [delta.old_file().path(), delta.new_file().path()])
+            .flatten()
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let mut options = CheckoutBuilder::new();
+        options.force().remove_untracked(true);
+        for path in &paths {
+            options.path(path.as_path());
+        }
+        match &head {
+            Some(head) => self.git_repo.checkout_tree(head.as_object(), Some(&mut options)),
+            None => Ok(()),
+        }
+    }
+
+    // The worktree of the same repo that has the reference as HEAD, other than this one.
+    // git refuses to move HEAD to a branch that's checked out somewhere else.
+    fn worktree_with_head(&self, reference: &str) -> Option<PathBuf> {
+        let git_dir = self.git_repo.path();
+        // Linked worktrees point to the main repo with the "commondir" file.
+        let common_dir = match std::fs::read_to_string(git_dir.join("commondir")) {
+            Ok(dir) => git_dir.join(dir.trim()),
+            Err(_) => git_dir.to_owned(),
+        };
+        let main = Repository::open(&common_dir).ok()?;
+        let linked = main
+            .worktrees()
+            .ok()?
+            .iter()
+            .flatten()
+            .filter_map(|name| Repository::open_from_worktree(&main.find_worktree(name).ok()?).ok())
+            .collect::<Vec<_>>();
+        let same_dir = |a: &Path, b: &Path| a.canonicalize().ok() == b.canonicalize().ok();
+        std::iter::once(&main)
+            .chain(linked.iter())
+            .filter(|repo| !repo.is_bare() && !same_dir(repo.path(), git_dir))
+            .find(|repo| repo.head().ok().and_then(|head| head.name().map(String::from)).as_deref() == Some(reference))
+            // Without the trailing "/" of the workdir.
+            .map(|repo| repo.workdir().unwrap_or_else(|| repo.path()).components().collect())
+    }
 }
 
 fn read_current_branch(repo: &Repository) -> String {
@@ -95,6 +685,33 @@ fn read_current_branch(repo: &Repository) -> String {
     head.unwrap_or("(no branch)").to_string()
 }
 
+fn read_ahead_behind(repo: &Repository, branch: &str) -> Option<(usize, usize)> {
+    let local = repo.find_branch(branch, BranchType::Local).ok()?;
+    let upstream = local.upstream().ok()?;
+    let local_oid = local.get().target()?;
+    let upstream_oid = upstream.get().target()?;
+    repo.graph_ahead_behind(local_oid, upstream_oid).ok()
+}
+
+pub fn read_trunk(repo: &Repository, settings: &RepoSettings) -> String {
+    if let Some(trunk) = &settings.trunk {
+        return trunk.to_string();
+    }
+    let origin_head = repo
+        .find_reference(ORIGIN_HEAD)
+        .ok()
+        .and_then(|r| r.symbolic_target().map(String::from))
+        .and_then(|target| target.strip_prefix(ORIGIN_PREFIX).map(String::from));
+    if let Some(trunk) = origin_head {
+        return trunk;
+    }
+    TRUNK_FALLBACKS
+        .iter()
+        .find(|name| repo.find_branch(name, BranchType::Local).is_ok())
+        .unwrap_or(&TRUNK_FALLBACKS[1])
+        .to_string()
+}
+
 fn read_branches(repo: &Repository) -> Vec<String> {
     repo
         .branches(None)
@@ -106,17 +723,567 @@ fn read_branches(repo: &Repository) -> Vec<String> {
         .collect::<Vec<String>>()
 }
 
-pub fn read_status(repo: &Repository) -> Status {
+fn short_id(oid: git2::Oid) -> String {
+    oid.to_string()[..7].to_string()
+}
+
+fn diff_options(path: &str) -> DiffOptions {
+    let mut options = DiffOptions::new();
+    options
+        .pathspec(path)
+        .disable_pathspec_match(true)
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .show_untracked_content(true);
+    options
+}
+
+// Binary files have no hunks.
+fn read_hunks(diff: &Diff, staged: bool) -> ReposResult<Vec<DiffHunk>> {
+    let mut hunks = vec![];
+    for delta in 0..diff.deltas().len() {
+        let patch = match Patch::from_diff(diff, delta)? {
+            Some(patch) => patch,
+            None => continue,
+        };
+        for index in 0..patch.num_hunks() {
+            let (hunk, line_count) = patch.hunk(index)?;
+            let lines = (0..line_count)
+                .map(|line| {
+                    let line = patch.line_in_hunk(index, line)?;
+                    let content = String::from_utf8_lossy(line.content());
+                    Ok(format!("{}{}", line.origin(), content.trim_matches('\n')))
+                })
+                .collect::<Result<Vec<_>, git2::Error>>()?;
+            hunks.push(DiffHunk {
+                staged,
+                header: String::from_utf8_lossy(hunk.header()).trim_end().to_string(),
+                lines,
+            });
+        }
+    }
+    Ok(hunks)
+}
+
+fn read_stashes(repo: &mut Repository) -> ReposResult<Vec<String>> {
+    let mut stashes = vec![];
+    repo.stash_foreach(|_, message, _| {
+        stashes.push(message.to_string());
+        true
+    })?;
+    Ok(stashes)
+}
+
+fn read_remote_branches(repo: &Repository, local_branches: &[String]) -> Vec<String> {
+    let branches = match repo.branches(Some(BranchType::Remote)) {
+        Ok(branches) => branches,
+        Err(_) => return vec![],
+    };
+    branches
+        .flatten()
+        .filter_map(|(branch, _)| branch.name().ok().flatten().map(String::from))
+        .filter(|name| !name.ends_with("/HEAD"))
+        .filter(|name| !local_branches.iter().any(|local| local == local_branch_name(name)))
+        .collect()
+}
+
+// "origin/feature" is "feature".
+fn local_branch_name(remote_branch: &str) -> &str {
+    remote_branch
+        .split_once('/')
+        .map(|(_, name)| name)
+        .unwrap_or(remote_branch)
+}
+
+pub fn read_status(repo: &Repository, settings: &RepoSettings) -> ReposResult<Status> {
     let mut status_options = StatusOptions::new();
     status_options.include_untracked(true);
-    status_options.include_ignored(INCLUDE_IGNORED);
+    status_options.include_ignored(settings.include_ignored);
     let mut stats = repo
-        .statuses(Some(&mut status_options))
-        .unwrap()
+        .statuses(Some(&mut status_options))?
         .iter()
         .map(|f| f.status())
         .collect::<Vec<_>>();
     stats.sort_unstable();
     stats.dedup();
-    Status::new().set_from_vec(stats)
+    Ok(Status::new().set_from_vec(stats))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutils::*;
+
+    #[test]
+    fn detecting_repo_kinds() {
+        let root = tempfile::tempdir().unwrap();
+        let main = init_repo(&root.path().join("main"));
+        main.worktree("linked", &root.path().join("linked"), None).unwrap();
+        init_gitfile_repo(&root.path().join("sub"), &root.path().join("modules/sub"));
+        Repository::init_bare(root.path().join("bare.git")).unwrap();
+        let kind = |dir: &str| open_repo(&root.path().join(dir)).kind();
+        assert_eq!(kind("main"), RepoKind::Normal);
+        assert_eq!(kind("linked"), RepoKind::Worktree);
+        assert_eq!(kind("sub"), RepoKind::GitFile);
+        assert_eq!(kind("bare.git"), RepoKind::Bare);
+    }
+
+    #[test]
+    fn detecting_trunk() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("repo");
+        let repo = init_repo(&path);
+        assert_eq!(open_repo(&path).trunk(), "master");
+        assert!(open_repo(&path).is_on_trunk());
+
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.branch("main", &head, false).unwrap();
+        assert_eq!(open_repo(&path).trunk(), "main");
+        assert!(!open_repo(&path).is_on_trunk());
+
+        repo.reference("refs/remotes/origin/develop", head.id(), false, "").unwrap();
+        repo.reference_symbolic(ORIGIN_HEAD, "refs/remotes/origin/develop", false, "").unwrap();
+        assert_eq!(open_repo(&path).trunk(), "develop");
+
+        let settings = RepoSettings {
+            trunk: Some("trunk".to_string()),
+            ..Config::default().repo_settings(&path)
+        };
+        assert_eq!(Repo::new(&path, &settings).unwrap().trunk(), "trunk");
+    }
+
+    #[test]
+    fn failing_on_an_unreadable_status() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("repo");
+        init_repo(&path);
+        std::fs::write(path.join(".git/index"), "broken").unwrap();
+        let settings = Config::default().repo_settings(&path);
+        let error = Repo::new(&path, &settings).err().unwrap().to_string();
+        assert!(error.contains("index"), "{}", error);
+    }
+
+    #[test]
+    fn counting_commits_ahead_and_behind_upstream() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("repo");
+        let repo = init_repo(&path);
+        assert_eq!(open_repo(&path).status().ahead_behind(), None);
+
+        let base = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.remote("origin", "file:///nowhere").unwrap();
+        repo.reference("refs/remotes/origin/master", base.id(), false, "").unwrap();
+        repo.find_branch("master", BranchType::Local)
+            .unwrap()
+            .set_upstream(Some("origin/master"))
+            .unwrap();
+        repo.branch("dev", &base, false)
+            .unwrap()
+            .set_upstream(Some("origin/master"))
+            .unwrap();
+        commit_file(&repo, "a", "a", "Local 1.");
+        commit_file(&repo, "b", "b", "Local 2.");
+        assert_eq!(open_repo(&path).status().ahead_behind(), Some((2, 0)));
+        assert_eq!(open_repo(&path).status().status_type(), StatusType::Unsynced);
+        assert_eq!(open_repo(&path).branch_ahead_behind("dev"), None);
+
+        let settings = RepoSettings {
+            ahead_behind_all_branches: true,
+            ..Config::default().repo_settings(&path)
+        };
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.reference("refs/remotes/origin/master", head.id(), true, "").unwrap();
+        let all = Repo::new(&path, &settings).unwrap();
+        assert_eq!(all.status().ahead_behind(), Some((0, 0)));
+        assert_eq!(all.branch_ahead_behind("dev"), Some((0, 2)));
+    }
+
+    #[test]
+    fn creating_branches() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("repo");
+        let repo = init_repo(&path);
+        let init = repo.head().unwrap().target().unwrap();
+        open_repo(&path).create_branch("base", None).unwrap();
+        let head = commit_file(&repo, "a", "a", "Second.");
+
+        open_repo(&path).create_branch("from-head", None).unwrap();
+        open_repo(&path).create_branch("from-base", Some("base")).unwrap();
+        let target = |name| repo.find_branch(name, BranchType::Local).unwrap().get().target().unwrap();
+        assert_eq!(target("from-head"), head);
+        assert_eq!(target("from-base"), init);
+        assert_eq!(open_repo(&path).current_branch(), "master");
+
+        assert!(open_repo(&path).create_branch("base", None).is_err());
+        assert!(open_repo(&path).create_branch("bad name", None).is_err());
+        assert!(open_repo(&path).create_branch("x", Some("missing")).is_err());
+    }
+
+    #[test]
+    fn deleting_branches() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("repo");
+        let repo = init_repo(&path);
+        open_repo(&path).create_branch("merged", None).unwrap();
+        open_repo(&path).create_branch("feature", None).unwrap();
+        repo.set_head("refs/heads/feature").unwrap();
+        let first = commit_file(&repo, "a", "a", "First.");
+        commit_file(&repo, "b", "b", "Second.");
+        repo.set_head("refs/heads/master").unwrap();
+
+        let unmerged = open_repo(&path).unmerged_commits("feature").unwrap();
+        assert_eq!(unmerged.len(), 2);
+        assert!(unmerged[0].ends_with(" Second."));
+        assert_eq!(unmerged[1], format!("{} First.", &first.to_string()[..7]));
+        assert!(open_repo(&path).unmerged_commits("merged").unwrap().is_empty());
+
+        assert!(open_repo(&path).delete_branch("master", true).is_err());
+        assert!(open_repo(&path).delete_branch("feature", false).is_err());
+        open_repo(&path).delete_branch("merged", false).unwrap();
+        open_repo(&path).delete_branch("feature", true).unwrap();
+        assert_eq!(open_repo(&path).branches(), &vec!["master".to_string()]);
+    }
+
+    #[test]
+    fn renaming_branches() {
+        let root = tempfile::tempdir().unwrap();
+        let (remote, _) = init_remote(root.path());
+        let path = root.path().join("clone");
+        let clone = clone_repo(&remote, &path);
+        open_repo(&path).create_branch("dev", None).unwrap();
+        let repo = open_repo(&path);
+
+        repo.rename_branch("master", "work").unwrap();
+        let renamed = open_repo(&path);
+        assert_eq!(renamed.current_branch(), "work");
+        assert_eq!(renamed.branches(), &vec!["dev".to_string(), "work".to_string()]);
+        assert_eq!(clone.head().unwrap().shorthand(), Some("work"));
+        let config = clone.config().unwrap().snapshot().unwrap();
+        assert_eq!(config.get_str("branch.work.merge").unwrap(), "refs/heads/master");
+        assert!(config.get_str("branch.master.merge").is_err());
+        assert_eq!(open_repo(&path).status().ahead_behind(), Some((0, 0)));
+
+        assert!(repo.rename_branch("dev", "work").is_err());
+        assert!(repo.rename_branch("dev", "bad name").is_err());
+        assert_eq!(open_repo(&path).branches(), &vec!["dev".to_string(), "work".to_string()]);
+    }
+
+    #[test]
+    fn checking_out_branches() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("repo");
+        let repo = init_repo(&path);
+        open_repo(&path).create_branch("feature", None).unwrap();
+        commit_file(&repo, "README", "master\n", "On master.");
+
+        open_repo(&path).checkout_branch("feature").unwrap();
+        assert_eq!(open_repo(&path).current_branch(), "feature");
+        assert_eq!(std::fs::read_to_string(path.join("README")).unwrap(), "init\n");
+        assert_eq!(open_repo(&path).status().status_type(), StatusType::Clean);
+
+        std::fs::write(path.join("README"), "local\n").unwrap();
+        let error = open_repo(&path).checkout_branch("master").unwrap_err();
+        assert!(matches!(&error, CheckoutError::Conflict(files) if files == &["README"]));
+        assert_eq!(open_repo(&path).current_branch(), "feature");
+        assert_eq!(std::fs::read_to_string(path.join("README")).unwrap(), "local\n");
+
+        let error = open_repo(&path).checkout_branch("missing").unwrap_err();
+        assert_eq!(error.to_string(), "No branch missing.");
+        assert!(!open_repo(&path).is_detached());
+        repo.set_head_detached(repo.head().unwrap().target().unwrap()).unwrap();
+        assert!(open_repo(&path).is_detached());
+        let error = open_repo(&path).checkout_branch(NO_BRANCH).unwrap_err();
+        assert!(matches!(error, CheckoutError::DetachedHead));
+    }
+
+    #[test]
+    fn checking_out_a_branch_of_another_worktree() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("main");
+        let repo = init_repo(&path);
+        open_repo(&path).create_branch("other", None).unwrap();
+        repo.set_head("refs/heads/other").unwrap();
+        commit_file(&repo, "only_on_other", "other\n", "Other.");
+        repo.set_head("refs/heads/master").unwrap();
+        repo.checkout_head(Some(CheckoutBuilder::new().force())).unwrap();
+        let linked = root.path().join("linked");
+        let reference = repo.find_reference("refs/heads/other").unwrap();
+        let mut options = git2::WorktreeAddOptions::new();
+        options.reference(Some(&reference));
+        repo.worktree("linked", &linked, Some(&options)).unwrap();
+
+        match open_repo(&path).checkout_branch("other").unwrap_err() {
+            CheckoutError::CheckedOutElsewhere(branch, worktree) => {
+                assert_eq!(branch, "other");
+                assert_eq!(worktree.canonicalize().unwrap(), linked.canonicalize().unwrap());
+            }
+            error => panic!("not the other worktree: {}", error),
+        }
+        assert!(!path.join("only_on_other").exists());
+        assert!(open_repo(&path).file_statuses().unwrap().is_empty());
+        let error = open_repo(&linked).checkout_branch("master").unwrap_err();
+        assert!(matches!(error, CheckoutError::CheckedOutElsewhere(..)), "{}", error);
+    }
+
+    #[test]
+    fn restoring_the_files_when_head_cant_be_moved() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("repo");
+        let repo = init_repo(&path);
+        open_repo(&path).create_branch("other", None).unwrap();
+        repo.set_head("refs/heads/other").unwrap();
+        commit_file(&repo, "only_on_other", "other\n", "Other.");
+        repo.set_head("refs/heads/master").unwrap();
+        repo.checkout_head(Some(CheckoutBuilder::new().force())).unwrap();
+        std::fs::write(path.join("README"), "local change\n").unwrap();
+        std::fs::write(path.join(".git/HEAD.lock"), "").unwrap();
+
+        assert!(open_repo(&path).checkout_branch("other").is_err());
+        assert!(!path.join("only_on_other").exists());
+        assert_eq!(std::fs::read_to_string(path.join("README")).unwrap(), "local change\n");
+        let files = open_repo(&path).file_statuses().unwrap();
+        assert_eq!(files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), vec!["README"]);
+        assert!(!files[0].is_staged());
+    }
+
+    #[test]
+    fn checking_out_remote_branches() {
+        let root = tempfile::tempdir().unwrap();
+        let (remote, seed) = init_remote(root.path());
+        open_repo(&root.path().join("seed")).create_branch("feature", None).unwrap();
+        push(&seed, "feature");
+        let path = root.path().join("clone");
+        clone_repo(&remote, &path);
+        assert_eq!(open_repo(&path).remote_branches(), &vec!["origin/feature".to_string()]);
+
+        assert_eq!(open_repo(&path).checkout_remote_branch("origin/feature").unwrap(), "feature");
+        let repo = open_repo(&path);
+        assert_eq!(repo.current_branch(), "feature");
+        assert_eq!(repo.status().ahead_behind(), Some((0, 0)));
+        assert!(repo.remote_branches().is_empty());
+        assert!(repo.checkout_remote_branch("origin/master").is_err());
+    }
+
+    #[test]
+    fn stashing_changes() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("repo");
+        init_repo(&path);
+        std::fs::write(path.join("README"), "changed\n").unwrap();
+        std::fs::write(path.join("new"), "new\n").unwrap();
+
+        open_repo(&path).stash("wip").unwrap();
+        let repo = open_repo(&path);
+        assert_eq!(repo.status().status_type(), StatusType::Clean);
+        assert_eq!(repo.stash_count(), 1);
+        assert_eq!(repo.stashes().unwrap(), vec!["On master: wip".to_string()]);
+
+        repo.apply_stash(0, false).unwrap();
+        assert_eq!(std::fs::read_to_string(path.join("new")).unwrap(), "new\n");
+        assert!(repo.apply_stash(0, true).is_err());
+        assert_eq!(open_repo(&path).stash_count(), 1);
+        repo.drop_stash(0).unwrap();
+        assert_eq!(open_repo(&path).stash_count(), 0);
+        assert_eq!(open_repo(&path).status().status_type(), StatusType::Dirty);
+    }
+
+    #[test]
+    fn stashing_before_checkout() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("repo");
+        let repo = init_repo(&path);
+        open_repo(&path).create_branch("feature", None).unwrap();
+        commit_file(&repo, "README", "master\n", "On master.");
+        std::fs::write(path.join("README"), "local\n").unwrap();
+        assert!(open_repo(&path).checkout_branch("feature").is_err());
+
+        open_repo(&path).stash_and_checkout("feature").unwrap();
+        let feature = open_repo(&path);
+        assert_eq!(feature.current_branch(), "feature");
+        assert_eq!(feature.status().status_type(), StatusType::Clean);
+        assert_eq!(feature.stashes().unwrap(), vec!["On master: repos: before checkout of feature".to_string()]);
+
+        feature.drop_stash(0).unwrap();
+        std::fs::write(path.join("README"), "again\n").unwrap();
+        assert!(open_repo(&path).stash_and_checkout("missing").is_err());
+        assert_eq!(std::fs::read_to_string(path.join("README")).unwrap(), "again\n");
+        assert_eq!(open_repo(&path).stash_count(), 0);
+    }
+
+    #[test]
+    fn auto_stashing_per_branch() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("repo");
+        let repo = init_repo(&path);
+        open_repo(&path).create_branch("feature", None).unwrap();
+        std::fs::write(path.join("README"), "master work\n").unwrap();
+
+        let notes = open_repo(&path).checkout_with_auto_stash("feature").unwrap();
+        assert_eq!(notes, "stashed the changes of master");
+        assert_eq!(open_repo(&path).status().status_type(), StatusType::Clean);
+        std::fs::write(path.join("new"), "feature work\n").unwrap();
+
+        let notes = open_repo(&path).checkout_with_auto_stash("master").unwrap();
+        assert_eq!(notes, "stashed the changes of feature, applied the changes of master");
+        assert_eq!(std::fs::read_to_string(path.join("README")).unwrap(), "master work\n");
+        assert!(!path.join("new").exists());
+        assert_eq!(open_repo(&path).stashes().unwrap(), vec!["On feature: repos-autostash:feature".to_string()]);
+
+        // The stash of master conflicts with a new commit on master.
+        open_repo(&path).checkout_with_auto_stash("feature").unwrap();
+        let commit = commit_file(&repo, "README", "feature\n", "Conflicting.");
+        repo.reference("refs/heads/master", commit, true, "").unwrap();
+        std::fs::remove_file(path.join("new")).unwrap();
+        let notes = open_repo(&path).checkout_with_auto_stash("master").unwrap();
+        assert_eq!(notes, "applying stash@{0} of master failed: The stash conflicts, resolve the conflicts. The stash is kept.");
+        assert!(std::fs::read_to_string(path.join("README")).unwrap().starts_with("<<<<<<<"));
+        assert_eq!(open_repo(&path).current_branch(), "master");
+        assert_eq!(open_repo(&path).stash_count(), 1);
+    }
+
+    #[test]
+    fn auto_stashing_skips_ignored_files() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("repo");
+        init_repo(&path);
+        open_repo(&path).create_branch("feature", None).unwrap();
+        std::fs::write(path.join(".git/info/exclude"), "*.log\n").unwrap();
+        std::fs::write(path.join("build.log"), "output\n").unwrap();
+        let settings = RepoSettings {
+            include_ignored: true,
+            trunk: None,
+            ahead_behind_all_branches: false,
+        };
+        let repo = Repo::new(&path, &settings).unwrap();
+        assert_eq!(repo.status().status_type(), StatusType::Dirty);
+
+        assert_eq!(repo.checkout_with_auto_stash("feature").unwrap(), "");
+        assert_eq!(open_repo(&path).current_branch(), "feature");
+        assert_eq!(open_repo(&path).stash_count(), 0);
+        assert!(path.join("build.log").exists());
+    }
+
+    #[test]
+    fn reading_the_log() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("repo");
+        let repo = init_repo(&path);
+        open_repo(&path).create_branch("old", None).unwrap();
+        commit_file(&repo, "a", "a", "Second.");
+        let third = commit_file(&repo, "b", "b", "Third.");
+
+        let log = open_repo(&path).log("HEAD", 0, 2).unwrap();
+        assert_eq!(log.iter().map(|c| c.summary.as_str()).collect::<Vec<_>>(), ["Third.", "Second."]);
+        assert_eq!(log[0].id, third.to_string()[..7]);
+        assert_eq!(log[0].author, "Test User");
+        let log = open_repo(&path).log("HEAD", 2, 10).unwrap();
+        assert_eq!(log.iter().map(|c| c.summary.as_str()).collect::<Vec<_>>(), ["Init."]);
+        assert_eq!(open_repo(&path).log("refs/heads/old", 0, 10).unwrap().len(), 1);
+        assert!(open_repo(&path).log("refs/heads/missing", 0, 10).is_err());
+    }
+
+    #[test]
+    fn staging_and_unstaging_files() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("repo");
+        init_repo(&path);
+        std::fs::write(path.join("README"), "changed\n").unwrap();
+        std::fs::create_dir(path.join("dir")).unwrap();
+        std::fs::write(path.join("dir/new"), "new\n").unwrap();
+
+        let state = |file: &str| {
+            let files = open_repo(&path).file_statuses().unwrap();
+            let file = files.into_iter().find(|f| f.path == file).unwrap();
+            format!("{}{}", file.index, file.worktree)
+        };
+        assert_eq!(state("README"), " M");
+        assert_eq!(state("dir/new"), "??");
+
+        let repo = open_repo(&path);
+        repo.stage_file("README").unwrap();
+        repo.stage_file("dir/new").unwrap();
+        assert_eq!(state("README"), "M ");
+        assert_eq!(state("dir/new"), "A ");
+        std::fs::write(path.join("README"), "changed again\n").unwrap();
+        assert_eq!(state("README"), "MM");
+
+        repo.unstage_file("README").unwrap();
+        repo.unstage_file("dir/new").unwrap();
+        assert_eq!(state("README"), " M");
+        assert_eq!(state("dir/new"), "??");
+
+        std::fs::remove_file(path.join("README")).unwrap();
+        repo.stage_file("README").unwrap();
+        assert_eq!(state("README"), "D ");
+    }
+
+    #[test]
+    fn staging_a_hunk() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("repo");
+        init_repo(&path);
+        let lines = (1..=20).map(|i| i.to_string()).collect::<Vec<_>>();
+        commit_file(&Repository::open(&path).unwrap(), "numbers", &(lines.join("\n") + "\n"), "numbers");
+        let repo = open_repo(&path);
+        let mut changed = lines.clone();
+        changed[0] = "one".to_string();
+        changed[19] = "twenty".to_string();
+        std::fs::write(path.join("numbers"), changed.join("\n") + "\n").unwrap();
+
+        let hunks = repo.file_diff("numbers").unwrap();
+        assert_eq!(hunks.len(), 2);
+        assert!(hunks.iter().all(|h| !h.staged));
+        assert!(hunks[1].lines.contains(&"+twenty".to_string()));
+
+        repo.stage_hunk("numbers", 1).unwrap();
+        let hunks = repo.file_diff("numbers").unwrap();
+        assert_eq!(hunks.len(), 2);
+        assert!(hunks[0].staged);
+        assert!(hunks[0].lines.contains(&"-20".to_string()));
+        assert!(hunks[0].lines.contains(&"+twenty".to_string()));
+        assert!(!hunks[1].staged);
+        assert!(hunks[1].lines.contains(&"+one".to_string()));
+
+        std::fs::write(path.join("new"), "new\n").unwrap();
+        repo.stage_hunk("new", 0).unwrap();
+        let hunks = repo.file_diff("new").unwrap();
+        assert_eq!(hunks.len(), 1);
+        assert!(hunks[0].staged);
+        assert_eq!(hunks[0].lines, vec!["+new".to_string()]);
+    }
+
+    #[test]
+    fn committing_the_staged_files() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("repo");
+        let git_repo = init_repo(&path);
+        let mut config = git_repo.config().unwrap();
+        config.set_str("user.name", "Ada").unwrap();
+        config.set_str("user.email", "ada@example.com").unwrap();
+        let repo = open_repo(&path);
+        assert_eq!(repo.commit("empty").unwrap_err().to_string(), "Nothing staged to commit.");
+
+        std::fs::write(path.join("README"), "fixed\n").unwrap();
+        std::fs::write(path.join("other"), "not staged\n").unwrap();
+        repo.stage_file("README").unwrap();
+        let id = repo.commit("Fix the readme").unwrap();
+
+        let head = git_repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(short_id(head.id()), id);
+        assert_eq!(head.summary(), Some("Fix the readme"));
+        assert_eq!(head.author().name(), Some("Ada"));
+        let files = open_repo(&path).file_statuses().unwrap();
+        assert_eq!(files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), vec!["other"]);
+    }
+
+    #[test]
+    fn naming_repos_after_their_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let main = init_repo(&root.path().join("main"));
+        main.worktree("linked", &root.path().join("feature-x"), None).unwrap();
+        assert_eq!(open_repo(&root.path().join("feature-x")).name(), "feature-x");
+        open_repo(&root.path().join("feature-x")).create_branch("other", None).unwrap();
+        open_repo(&root.path().join("feature-x")).checkout_branch("other").unwrap();
+        assert_eq!(open_repo(&root.path().join("feature-x")).current_branch(), "other");
+    }
 }
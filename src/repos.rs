@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use std::collections::HashSet;
 
 #[derive(PartialEq)]
 pub enum RepoSort {
@@ -7,75 +8,568 @@ pub enum RepoSort {
     CurrentBranch,
 }
 
-pub fn run(root_path: PathBuf) -> ReposResult<()> {
+// How the results of a job are shown when it's finished.
+#[derive(PartialEq)]
+enum JobReport {
+    Message,
+    Popup,
+    // The output of a command run in the repos.
+    Output,
+}
+
+// A cell in the branches column.
+#[derive(Clone, Copy)]
+enum BranchCell<'a> {
+    Local(&'a str),
+    // Like "origin/feature", only shown when toggled.
+    Remote(&'a str),
+}
+
+// An action waiting for an answer, the question is in the message line.
+enum Confirm {
+    // Pushing these repos, some of them are on the trunk.
+    PushTrunk(Vec<PathBuf>),
+    // Checking out the new branch in these repos.
+    CheckoutBranch(Vec<PathBuf>, String),
+    // Deleting the branch of the repo, forced once the unmerged commits were shown.
+    DeleteBranch(PathBuf, String, bool),
+    // Switching these repos to the branch, creating it where it's missing.
+    SwitchCreate(Vec<PathBuf>, String),
+    // Checking out the branch in a dirty repo, stashing the changes first.
+    StashCheckout(PathBuf, String),
+    DropStash(PathBuf, usize),
+}
+
+// What's done with the text typed in the prompt.
+enum PromptAction {
+    // New branch in these repos, from the start point branch or from HEAD.
+    CreateBranch(Vec<PathBuf>, Option<String>),
+    // New name for the branch of the repo.
+    RenameBranch(PathBuf, String),
+    // Branch to switch these repos to.
+    Switch(Vec<PathBuf>),
+    // Commit message, the staged files are listed in the popup.
+    Commit(PathBuf),
+    // Command to run in these repos.
+    Exec(Vec<PathBuf>),
+}
+
+// What the keys of the popup act on, besides moving and closing it.
+enum PopupActions {
+    // The stashes of the repo.
+    Stashes(PathBuf),
+    // The changed files of the repo, with their paths.
+    Files(PathBuf, Vec<String>),
+    // The diff of a file of the repo.
+    Diff(PathBuf, String, Vec<HunkLine>),
+    // The open actions of the config, for the repo.
+    Open(PathBuf),
+}
+
+// Where a hunk starts in the diff popup.
+#[derive(Clone)]
+struct HunkLine {
+    line: usize,
+    staged: bool,
+    // Counted separately for the staged and the unstaged hunks.
+    index: usize,
+}
+
+// What the tui keeps between the refreshes.
+struct State {
+    repo_sort: RepoSort,
+    // Running on many repos in the background, like fetch.
+    job: Option<(BatchJob, JobReport)>,
+    // Shown instead of the repos while it's open.
+    popup: Option<Popup>,
+    popup_actions: Option<PopupActions>,
+    // Counts the refreshes, for the spinners.
+    frame: usize,
+    // Repos the actions are run for, instead of the selected one.
+    marked: HashSet<PathBuf>,
+    show_remote_branches: bool,
+    // Scroll position of the log pane, it's shown when set.
+    log: Option<usize>,
+    confirm: Option<Confirm>,
+    prompt: Option<(Prompt, PromptAction)>,
+}
+
+pub fn run(args: Args) -> ReposResult<()> {
     enable_raw_mode()?;
-    let mut tui = Tui::new();
+    let mut tui = Tui::new(&args.config);
     tui.print(&format!("{}", crossterm::cursor::Hide))?;
-    let mut repo_sort = RepoSort::Alpha;
+    // The terminal is restored on errors too, like a repo that can't be read.
+    let result = run_loop(&args, &mut tui);
+    tui.print(&format!("{}", crossterm::cursor::Show))?;
+    disable_raw_mode()?;
+    result
+}
+
+fn run_loop(args: &Args, tui: &mut Tui) -> ReposResult<()> {
+    let config = &args.config;
+    let mut state = State {
+        repo_sort: RepoSort::Alpha,
+        job: None,
+        popup: None,
+        popup_actions: None,
+        frame: 0,
+        marked: HashSet::new(),
+        show_remote_branches: false,
+        log: None,
+        confirm: None,
+        prompt: None,
+    };
 
     loop {
-        tui.clear()?;
-        let repos = collect_repos(&root_path, &repo_sort)?;
-        tui.set_max_selected_column(repos[tui.selected_coord().get_row() as usize].branches().len() as u16 + 1);
-
-        for repo in repos.iter() {
-            if repo.is_on_master() && repo.status().status_type() == StatusType::Clean {
-                tui.set_cell_style(CellStyle::CleanMaster);
-            } else if repo.is_on_master() && repo.status().status_type() != StatusType::Clean {
-                tui.set_cell_style(CellStyle::DirtyMaster);
-            } else if !repo.is_on_master() && repo.status().status_type() == StatusType::Clean {
-                tui.set_cell_style(CellStyle::CleanBranch);
-            } else if !repo.is_on_master() && repo.status().status_type() != StatusType::Clean {
-                tui.set_cell_style(CellStyle::DirtyBranch);
-            }
-
-            tui.print(&text_to_width(repo.name(), &(REPO_NAME_WIDTH as usize)))?;
-            tui.print(&format!("{}", repo.status()))?;
-
-            for branch in repo.branches() {
-                if branch == repo.current_branch() {
-                    tui.cell_style = CellStyle::CurrentBranch;
-                } else {
-                    tui.cell_style = CellStyle::Branch;
-                }
-                tui.print(&limit_text(branch, &MAX_BRANCH_NAME_WIDTH))?;
+        state.frame += 1;
+        if state.job.as_ref().is_some_and(|(job, _)| job.is_finished()) {
+            let (job, report) = state.job.take().unwrap();
+            let level = if job.failed_count() > 0 { MessageLevel::Error } else { MessageLevel::Info };
+            tui.set_message(&job.summary(), level);
+            match report {
+                JobReport::Message => {}
+                JobReport::Popup => state.popup = Some(job.results_popup()),
+                JobReport::Output => state.popup = Some(exec_popup(&job.results())),
+            }
+            if report != JobReport::Message {
+                state.popup_actions = None;
             }
-            tui.new_line()?;
         }
-
-        let sel_cell_branch = if tui.selected_coord().get_column().to_column() == Column::Branches {
-            repos[tui.selected_coord().get_row() as usize].branches()[tui.selected_coord().get_column() as usize - 2].as_str()
+        tui.clear()?;
+        let repos = collect_repos(&args.sources, config, &state.repo_sort)?;
+        if let Some(popup) = &state.popup {
+            tui.print_popup(popup)?;
+            if let Some((prompt, _)) = &state.prompt {
+                tui.print_popup_prompt(prompt)?;
+            }
         } else {
-            ""
-        };
-
-        tui.print_dev_dir(root_path.to_str().unwrap())?;
-        tui.print_status(
-            repos[tui.selected_coord().get_row() as usize].name(),
-            repos[tui.selected_coord().get_row() as usize].current_branch(),
-            sel_cell_branch,
-        )?;
-
+            print_repos(tui, &repos, args, &state)?;
+            if let Some((prompt, _)) = &state.prompt {
+                tui.print_prompt(prompt)?;
+            }
+        }
         tui.flush()?;
 
-        if poll(Duration::from_secs_f32(UPDATE_DELAY_SECS))? {
+        if poll(Duration::from_secs_f32(config.update_delay_secs))? {
             let event = read()?;
 
-            // quit.
-            if event == Event::Key(KeyCode::Char('q').into()) {
+            if let Some(confirm) = state.confirm.take() {
+                on_confirm_keypress_action(&event, confirm, tui, &repos, &mut state, config);
+            } else if let Some((prompt, action)) = state.prompt.take() {
+                on_prompt_keypress_action(&event, prompt, action, tui, &repos, &mut state, config);
+            } else if state.popup.is_some() {
+                on_popup_keypress_action(&event, tui, &repos, &mut state, config);
+            } else if event == Event::Key(KeyCode::Char('q').into()) {
+                // quit.
                 break;
             } else {
-                on_keypress_action(&event, &mut tui, &repos, &mut repo_sort)?;
+                on_keypress_action(&event, tui, &repos, &mut state, config)?;
+            };
+        }
+    }
+    Ok(())
+}
+
+fn print_repos(tui: &mut Tui, repos: &[Repo], args: &Args, state: &State) -> ReposResult<()> {
+    let config = &args.config;
+    tui.set_max_selected_column(branch_cells(&repos[tui.selected_coord().get_row() as usize], state).len() as u16 + 1);
+
+    let mut group = None;
+    for repo in repos.iter() {
+        if args.sources.len() > 1 && group != Some(repo.group()) {
+            group = Some(repo.group());
+            tui.print_header(&args.sources[repo.group()].title())?;
+        }
+        if repo.is_on_trunk() && repo.status().status_type() == StatusType::Clean {
+            tui.set_cell_style(CellStyle::CleanTrunk);
+        } else if repo.is_on_trunk() && repo.status().status_type() != StatusType::Clean {
+            tui.set_cell_style(CellStyle::DirtyTrunk);
+        } else if !repo.is_on_trunk() && repo.status().status_type() == StatusType::Clean {
+            tui.set_cell_style(CellStyle::CleanBranch);
+        } else if !repo.is_on_trunk() && repo.status().status_type() != StatusType::Clean {
+            tui.set_cell_style(CellStyle::DirtyBranch);
+        }
+
+        tui.print_marker(state.marked.contains(repo.path()))?;
+        let name = format!("{}{}", repo.name(), repo.kind().marker());
+        let job_marker = state
+            .job
+            .as_ref()
+            .and_then(|(job, _)| job.state(repo.path()))
+            .map(|job_state| job_state.marker(state.frame))
+            .unwrap_or_default();
+        let name_width = (config.repo_name_width as usize).saturating_sub(job_marker.len()).max(1);
+        tui.print(&format!("{}{}", text_to_width(&name, &name_width), job_marker))?;
+        let stashes = match repo.stash_count() {
+            0 => String::new(),
+            count => format!(" ${}", count),
+        };
+        tui.print(&format!("{}{}", repo.status(), stashes))?;
+
+        for cell in branch_cells(repo, state) {
+            let text = match cell {
+                BranchCell::Local(branch) => {
+                    if branch == repo.current_branch() {
+                        tui.cell_style = CellStyle::CurrentBranch;
+                    } else {
+                        tui.cell_style = CellStyle::Branch;
+                    }
+                    let ahead_behind = format_ahead_behind(repo.branch_ahead_behind(branch));
+                    format!("{}{}", limit_text(branch, &config.max_branch_name_width), ahead_behind)
+                }
+                BranchCell::Remote(branch) => {
+                    tui.cell_style = CellStyle::RemoteBranch;
+                    limit_text(branch, &config.max_branch_name_width)
+                }
             };
+            tui.print(&text)?;
         }
+        tui.new_line()?;
     }
 
-    tui.print(&format!("{}", crossterm::cursor::Show))?;
-    disable_raw_mode()?;
+    let sel_cell_branch = match selected_branch(tui, repos, state) {
+        Some((_, BranchCell::Local(branch) | BranchCell::Remote(branch))) => branch,
+        None => "",
+    };
+
+    let titles = args.sources.iter().map(|s| s.title()).collect::<Vec<_>>();
+    tui.print_dev_dir(&titles.join(", "))?;
+    tui.print_status(
+        repos[tui.selected_coord().get_row() as usize].name(),
+        repos[tui.selected_coord().get_row() as usize].current_branch(),
+        sel_cell_branch,
+        repos.iter().filter(|r| state.marked.contains(r.path())).count(),
+    )?;
+    if let Some(skip) = state.log {
+        print_log(tui, repos, state, config, skip)?;
+    }
     Ok(())
 }
 
-fn on_keypress_action(event: &Event, tui: &mut Tui, repos: &[Repo], repo_sort: &mut RepoSort) -> ReposResult<()>{
+// Commits of the selected branch cell, or of HEAD.
+fn print_log(tui: &mut Tui, repos: &[Repo], state: &State, config: &Config, skip: usize) -> ReposResult<()> {
+    let repo = &repos[tui.selected_coord().get_row() as usize];
+    let (title, reference) = match selected_branch(tui, repos, state) {
+        Some((_, BranchCell::Local(branch))) => (branch.to_string(), format!("refs/heads/{}", branch)),
+        Some((_, BranchCell::Remote(branch))) => (branch.to_string(), format!("refs/remotes/{}", branch)),
+        None => (repo.current_branch().to_string(), "HEAD".to_string()),
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+    let lines = match repo.log(&reference, skip, config.log_length) {
+        Ok(log) => log
+            .iter()
+            .map(|c| {
+                let time_ago = format_time_ago(now - c.time);
+                format!("{} {} {} {}", c.id, text_to_width(&time_ago, &15), text_to_width(&c.author, &20), c.summary)
+            })
+            .collect(),
+        Err(error) => vec![error.to_string()],
+    };
+    let title = format!("log: {} {} (J/K: scroll)", repo.name(), title);
+    tui.print_log(&title, &lines)
+}
+
+fn on_popup_keypress_action(event: &Event, tui: &mut Tui, repos: &[Repo], state: &mut State, config: &Config) {
+    match &state.popup_actions {
+        Some(PopupActions::Stashes(path)) => {
+            if let Some(repo) = repos.iter().find(|r| r.path() == path) {
+                on_stash_list_keypress_action(event, tui, repo, state);
+            }
+        }
+        Some(PopupActions::Files(path, files)) => {
+            let files = files.clone();
+            if let Some(repo) = repos.iter().find(|r| r.path() == path) {
+                on_file_list_keypress_action(event, tui, repo, state, &files);
+            }
+        }
+        Some(PopupActions::Diff(path, file, hunks)) => {
+            let (file, hunks) = (file.clone(), hunks.clone());
+            if let Some(repo) = repos.iter().find(|r| r.path() == path) {
+                on_diff_keypress_action(event, tui, repo, state, &file, &hunks);
+            }
+            return;
+        }
+        Some(PopupActions::Open(path)) => {
+            let repo = repos.iter().find(|r| r.path() == path);
+            if let (Some(repo), Some(popup)) = (repo, &state.popup) {
+                if *event == Event::Key(KeyCode::Enter.into()) {
+                    if let Some(action) = config.open_actions.get(popup.selected()) {
+                        open_with(tui, repo, action);
+                    }
+                    state.popup = None;
+                    state.popup_actions = None;
+                }
+            }
+        }
+        None => {}
+    }
+    let popup = match state.popup.as_mut() {
+        Some(popup) => popup,
+        None => return,
+    };
+    if *event == Event::Key(KeyCode::Up.into()) || *event == Event::Key(KeyCode::Char('k').into()) {
+        popup.go(Direction::Up);
+    }
+    if *event == Event::Key(KeyCode::Down.into()) || *event == Event::Key(KeyCode::Char('j').into()) {
+        popup.go(Direction::Down);
+    }
+    if *event == Event::Key(KeyCode::Esc.into()) || *event == Event::Key(KeyCode::Char('q').into()) {
+        state.popup = None;
+        state.popup_actions = None;
+    }
+}
+
+fn on_stash_list_keypress_action(event: &Event, tui: &mut Tui, repo: &Repo, state: &mut State) {
+    let index = match &state.popup {
+        Some(popup) => popup.selected(),
+        None => return,
+    };
+    let result = match event {
+        Event::Key(key) if key.code == KeyCode::Char('p') => repo.apply_stash(index, true).map(|_| "Popped"),
+        Event::Key(key) if key.code == KeyCode::Char('a') => repo.apply_stash(index, false).map(|_| "Applied"),
+        Event::Key(key) if key.code == KeyCode::Char('d') => {
+            if let Some(popup) = state.popup.as_mut() {
+                popup.help = format!("Drop stash@{{{}}}? y/n", index);
+            }
+            state.confirm = Some(Confirm::DropStash(repo.path().to_owned(), index));
+            return;
+        }
+        _ => return,
+    };
+    match result {
+        Ok(done) => {
+            tui.set_message(&format!("{} stash@{{{}}}.", done, index), MessageLevel::Info);
+            state.popup = None;
+            state.popup_actions = None;
+        }
+        Err(error) => tui.set_message(&error.to_string(), MessageLevel::Error),
+    }
+}
+
+fn on_file_list_keypress_action(event: &Event, tui: &mut Tui, repo: &Repo, state: &mut State, files: &[String]) {
+    let index = match &state.popup {
+        Some(popup) => popup.selected(),
+        None => return,
+    };
+    let file = match files.get(index) {
+        Some(file) => file,
+        None => return,
+    };
+    let result = match event {
+        Event::Key(key) if key.code == KeyCode::Char('s') => repo.stage_file(file).map(|_| "Staged"),
+        Event::Key(key) if key.code == KeyCode::Char('u') => repo.unstage_file(file).map(|_| "Unstaged"),
+        Event::Key(key) if key.code == KeyCode::Enter => {
+            open_diff(tui, repo, state, file);
+            return;
+        }
+        _ => return,
+    };
+    match result {
+        Ok(done) => {
+            tui.set_message(&format!("{} {}.", done, file), MessageLevel::Info);
+            open_file_list(tui, repo, state);
+            if let Some(popup) = state.popup.as_mut() {
+                popup.select(index);
+            }
+        }
+        Err(error) => tui.set_message(&error.to_string(), MessageLevel::Error),
+    }
+}
+
+// Handles the moving and closing too, closing goes back to the file list.
+fn on_diff_keypress_action(event: &Event, tui: &mut Tui, repo: &Repo, state: &mut State, file: &str, hunks: &[HunkLine]) {
+    let popup = match state.popup.as_mut() {
+        Some(popup) => popup,
+        None => return,
+    };
+    let selected = popup.selected();
+    // The hunk of the selected line.
+    let hunk = hunks.iter().rev().find(|h| h.line <= selected);
+    match event {
+        Event::Key(key) if key.code == KeyCode::Up || key.code == KeyCode::Char('k') => popup.go(Direction::Up),
+        Event::Key(key) if key.code == KeyCode::Down || key.code == KeyCode::Char('j') => popup.go(Direction::Down),
+        Event::Key(key) if key.code == KeyCode::Char('n') => {
+            if let Some(next) = hunks.iter().find(|h| h.line > selected) {
+                popup.select(next.line);
+            }
+        }
+        Event::Key(key) if key.code == KeyCode::Char('N') => {
+            if let Some(previous) = hunks.iter().rev().find(|h| h.line < hunk.map_or(selected, |h| h.line)) {
+                popup.select(previous.line);
+            } else if let Some(hunk) = hunk {
+                popup.select(hunk.line);
+            }
+        }
+        Event::Key(key) if key.code == KeyCode::Char('s') => match hunk {
+            Some(hunk) if hunk.staged => tui.set_message("The hunk is staged already.", MessageLevel::Info),
+            Some(hunk) => match repo.stage_hunk(file, hunk.index) {
+                Ok(()) => {
+                    tui.set_message(&format!("Staged a hunk of {}.", file), MessageLevel::Info);
+                    open_diff(tui, repo, state, file);
+                    if let Some(popup) = state.popup.as_mut() {
+                        popup.select(selected);
+                    }
+                }
+                Err(error) => tui.set_message(&error.to_string(), MessageLevel::Error),
+            },
+            None => {}
+        },
+        Event::Key(key) if key.code == KeyCode::Esc || key.code == KeyCode::Char('q') => {
+            open_file_list(tui, repo, state);
+            if let Some(PopupActions::Files(_, files)) = &state.popup_actions {
+                let index = files.iter().position(|f| f == file).unwrap_or_default();
+                if let Some(popup) = state.popup.as_mut() {
+                    popup.select(index);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn on_prompt_keypress_action(
+    event: &Event,
+    mut prompt: Prompt,
+    action: PromptAction,
+    tui: &mut Tui,
+    repos: &[Repo],
+    state: &mut State,
+    config: &Config,
+) {
+    let event = prompt.on_key(event);
+    if !matches!(event, PromptEvent::Editing) {
+        // Only the commit prompt has a popup, with the staged files.
+        state.popup = None;
+        state.popup_actions = None;
+    }
+    match event {
+        PromptEvent::Editing => state.prompt = Some((prompt, action)),
+        PromptEvent::Submitted(text) if !text.is_empty() => match action {
+            PromptAction::CreateBranch(paths, start_point) => create_branches(tui, repos, state, paths, start_point, text),
+            PromptAction::Switch(paths) => {
+                let missing = repos
+                    .iter()
+                    .filter(|r| paths.iter().any(|p| p == r.path()))
+                    .filter(|r| r.kind().can_checkout() && !r.branches().contains(&text))
+                    .filter(|r| remote_branch(r, &text).is_none())
+                    .count();
+                if missing == 0 {
+                    switch_branch(tui, repos, state, paths, text, false, config.auto_stash);
+                } else {
+                    let question = format!("Create {} in {} repo(s) that don't have it? y/n", text, missing);
+                    tui.set_message(&question, MessageLevel::Info);
+                    state.confirm = Some(Confirm::SwitchCreate(paths, text));
+                }
+            }
+            PromptAction::Exec(paths) => {
+                if can_start_job(tui, state) {
+                    let command = vec!["sh".to_string(), "-c".to_string(), text];
+                    let job = BatchJob::start("exec", paths, config.workers, move |path| exec_repo(path, &command));
+                    state.job = Some((job, JobReport::Output));
+                    tui.set_message("Running...", MessageLevel::Info);
+                }
+            }
+            PromptAction::Commit(path) => {
+                if let Some(repo) = repos.iter().find(|r| r.path() == path) {
+                    match repo.commit(&text) {
+                        Ok(id) => tui.set_message(&format!("Committed {} in {}.", id, repo.name()), MessageLevel::Info),
+                        Err(error) => tui.set_message(&format!("{}: {}", repo.name(), error), MessageLevel::Error),
+                    }
+                }
+            }
+            PromptAction::RenameBranch(_, branch) if branch == text => tui.set_message("Not renamed.", MessageLevel::Info),
+            PromptAction::RenameBranch(path, branch) => {
+                if let Some(repo) = repos.iter().find(|r| r.path() == path) {
+                    match repo.rename_branch(&branch, &text) {
+                        Ok(()) => tui.set_message(&format!("Renamed {} to {}.", branch, text), MessageLevel::Info),
+                        Err(error) => tui.set_message(&error.to_string(), MessageLevel::Error),
+                    }
+                }
+            }
+        },
+        _ => tui.set_message("Cancelled.", MessageLevel::Info),
+    }
+}
+
+fn on_confirm_keypress_action(
+    event: &Event,
+    confirm: Confirm,
+    tui: &mut Tui,
+    repos: &[Repo],
+    state: &mut State,
+    config: &Config,
+) {
+    if let Confirm::DeleteBranch(..) | Confirm::DropStash(..) = confirm {
+        // The unmerged commits or the stashes are listed in a popup.
+        state.popup = None;
+        state.popup_actions = None;
+    }
+    let answer = match event {
+        Event::Key(key) if key.code == KeyCode::Char('y') => true,
+        Event::Key(key) if key.code == KeyCode::Char('n') => false,
+        _ => {
+            tui.set_message("Cancelled.", MessageLevel::Info);
+            return;
+        }
+    };
+    match confirm {
+        Confirm::PushTrunk(paths) => start_push(tui, state, config, paths, answer),
+        Confirm::SwitchCreate(paths, branch) => {
+            switch_branch(tui, repos, state, paths, branch, answer, config.auto_stash)
+        }
+        Confirm::StashCheckout(path, branch) => {
+            if let Some(repo) = repos.iter().find(|r| r.path() == path) {
+                let result = match answer {
+                    true => repo.stash_and_checkout(&branch),
+                    false => repo.checkout_branch(&branch).map_err(Into::into),
+                };
+                match result {
+                    Ok(()) => tui.set_message(&format!("Checked out {}.", branch), MessageLevel::Info),
+                    Err(error) => tui.set_message(&format!("{}: {}", repo.name(), error), MessageLevel::Error),
+                }
+            }
+        }
+        Confirm::DropStash(path, index) => {
+            if let Some(repo) = repos.iter().find(|r| r.path() == path) {
+                if answer {
+                    match repo.drop_stash(index) {
+                        Ok(()) => tui.set_message(&format!("Dropped stash@{{{}}}.", index), MessageLevel::Info),
+                        Err(error) => tui.set_message(&error.to_string(), MessageLevel::Error),
+                    }
+                }
+                open_stash_list(tui, repo, state);
+            }
+        }
+        Confirm::DeleteBranch(..) if !answer => tui.set_message("Cancelled.", MessageLevel::Info),
+        Confirm::DeleteBranch(path, branch, force) => {
+            if let Some(repo) = repos.iter().find(|r| r.path() == path) {
+                delete_branch(tui, repo, state, branch, force);
+            }
+        }
+        Confirm::CheckoutBranch(_, branch) if !answer => tui.set_message(&format!("Created {}.", branch), MessageLevel::Info),
+        Confirm::CheckoutBranch(paths, branch) => {
+            let errors = repos
+                .iter()
+                .filter(|r| paths.iter().any(|p| p == r.path()))
+                .filter_map(|r| r.checkout_branch(&branch).err().map(|e| format!("{}: {}", r.name(), e)))
+                .collect::<Vec<_>>();
+            if errors.is_empty() {
+                tui.set_message(&format!("Checked out {}.", branch), MessageLevel::Info);
+            } else {
+                tui.set_message(&format!("Checkout failed in {}", errors.join(", ")), MessageLevel::Error);
+            }
+        }
+    }
+}
+
+fn on_keypress_action(event: &Event, tui: &mut Tui, repos: &[Repo], state: &mut State, config: &Config) -> ReposResult<()>{
+    if *event == Event::Key(KeyCode::Esc.into()) {
+        tui.clear_message();
+    }
+
     // Navogation.
     if *event == Event::Key(KeyCode::Up.into()) || *event == Event::Key(KeyCode::Char('k').into()) {
         tui.go(Direction::Up);
@@ -92,59 +586,566 @@ fn on_keypress_action(event: &Event, tui: &mut Tui, repos: &[Repo], repo_sort: &
 
     //Sorting.
     if *event == Event::Key(KeyCode::Char('s').into()) {
-        *repo_sort = match repo_sort {
+        state.repo_sort = match state.repo_sort {
             RepoSort::Alpha => RepoSort::Status,
             RepoSort::Status => RepoSort::CurrentBranch,
             RepoSort::CurrentBranch => RepoSort::Alpha,
         }
     }
 
+    // Remotes.
+    if *event == Event::Key(KeyCode::Char('f').into()) && can_start_job(tui, state) {
+        let paths = batch_repos(repos, state).iter().map(|r| r.path().to_owned()).collect();
+        let job = BatchJob::start("fetch", paths, config.workers, fetch_repo);
+        state.job = Some((job, JobReport::Message));
+        tui.set_message("Fetching...", MessageLevel::Info);
+    }
+    if *event == Event::Key(KeyCode::Char('p').into()) && can_start_job(tui, state) {
+        let paths = batch_repos(repos, state).iter().map(|r| r.path().to_owned()).collect();
+        let config = config.clone();
+        let job = BatchJob::start("pull", paths, config.workers, move |path| {
+            pull_repo(path, &config.repo_settings(path))
+        });
+        state.job = Some((job, JobReport::Popup));
+        tui.set_message("Pulling...", MessageLevel::Info);
+    }
+
+    if is_char(event, 'P') && can_start_job(tui, state) {
+        let targets = target_repos(tui, repos, state);
+        let paths = targets.iter().map(|r| r.path().to_owned()).collect();
+        let on_trunk = targets.iter().filter(|r| r.is_on_trunk()).map(|r| r.name()).collect::<Vec<_>>();
+        if on_trunk.is_empty() {
+            start_push(tui, state, config, paths, false);
+        } else {
+            let question = format!("Push the trunk of {} too? y: yes  n: skip them  esc: cancel", on_trunk.join(", "));
+            tui.set_message(&question, MessageLevel::Info);
+            state.confirm = Some(Confirm::PushTrunk(paths));
+        }
+    }
+
+    // Branches.
+    if *event == Event::Key(KeyCode::Char('c').into()) {
+        let selected = &repos[tui.selected_coord().get_row() as usize];
+        let targets = target_repos(tui, repos, state);
+        let paths = targets.iter().map(|r| r.path().to_owned()).collect::<Vec<_>>();
+        // The branch cell is only used as the start point for the selected repo.
+        let start_point = match selected_branch(tui, repos, state) {
+            Some((_, BranchCell::Local(branch))) if paths == [selected.path()] => Some(branch.to_string()),
+            _ => None,
+        };
+        let label = match (&start_point, paths.len()) {
+            (Some(branch), _) => format!("New branch from {}", branch),
+            (None, 1) => "New branch from HEAD".to_string(),
+            (None, count) => format!("New branch from HEAD in {} repos", count),
+        };
+        tui.clear_message();
+        state.prompt = Some((Prompt::new(&label), PromptAction::CreateBranch(paths, start_point)));
+    }
+
+    if *event == Event::Key(KeyCode::Char('d').into()) {
+        match selected_branch(tui, repos, state) {
+            Some((repo, BranchCell::Local(branch))) => ask_delete_branch(tui, repo, state, branch),
+            Some((_, BranchCell::Remote(_))) => tui.set_message("Only local branches can be deleted.", MessageLevel::Error),
+            None => {}
+        }
+    }
+
+    if *event == Event::Key(KeyCode::Char('r').into()) {
+        match selected_branch(tui, repos, state) {
+            // The placeholder of a detached or unborn HEAD is not a branch.
+            Some((repo, BranchCell::Local(branch)))
+                if branch == NO_BRANCH || (repo.is_detached() && branch == repo.current_branch()) =>
+            {
+                tui.set_message(&CheckoutError::DetachedHead.to_string(), MessageLevel::Error)
+            }
+            Some((repo, BranchCell::Local(branch))) => {
+                tui.clear_message();
+                let prompt = Prompt::new(&format!("Rename {}", branch)).with_input(branch);
+                state.prompt = Some((prompt, PromptAction::RenameBranch(repo.path().to_owned(), branch.to_string())));
+            }
+            Some((_, BranchCell::Remote(_))) => tui.set_message("Only local branches can be renamed.", MessageLevel::Error),
+            None => {}
+        }
+    }
+
+    if is_char(event, '!') {
+        let paths = batch_repos(repos, state).iter().map(|r| r.path().to_owned()).collect::<Vec<_>>();
+        tui.clear_message();
+        let label = format!("Run in {} repo(s)", paths.len());
+        state.prompt = Some((Prompt::new(&label), PromptAction::Exec(paths)));
+    }
+
+    if is_char(event, 'C') {
+        ask_commit(tui, &repos[tui.selected_coord().get_row() as usize], state);
+    }
+
+    if is_char(event, 'S') {
+        open_stash_list(tui, &repos[tui.selected_coord().get_row() as usize], state);
+    }
+
+    // Log.
+    if is_char(event, 'L') {
+        state.log = match state.log {
+            Some(_) => None,
+            None => Some(0),
+        };
+    }
+    if let Some(skip) = state.log.as_mut() {
+        if is_char(event, 'J') {
+            *skip += 1;
+        }
+        if is_char(event, 'K') {
+            *skip = skip.saturating_sub(1);
+        }
+    }
+
+    if is_char(event, 'R') {
+        state.show_remote_branches = !state.show_remote_branches;
+    }
+
+    if *event == Event::Key(KeyCode::Char('t').into()) {
+        let paths = batch_repos(repos, state).iter().map(|r| r.path().to_owned()).collect::<Vec<_>>();
+        tui.clear_message();
+        let label = format!("Switch {} repo(s) to branch", paths.len());
+        state.prompt = Some((Prompt::new(&label), PromptAction::Switch(paths)));
+    }
+
+    // Marks.
+    if *event == Event::Key(KeyCode::Char(' ').into()) {
+        let path = repos[tui.selected_coord().get_row() as usize].path();
+        if !state.marked.remove(path) {
+            state.marked.insert(path.to_owned());
+        }
+    }
+    if is_char(event, '*') {
+        state.marked = toggle_all_marks(repos, &state.marked);
+    }
+    if *event == Event::Key(KeyCode::Char('a').into()) {
+        let (marked, message) = cycle_marks(repos, &state.marked);
+        state.marked = marked;
+        tui.set_message(&message, MessageLevel::Info);
+    }
+
     // Action
     if *event == Event::Key(KeyCode::Enter.into()) {
         match tui.selected_coord().get_column().to_column() {
             Column::Name => {
                 let repo = &repos[tui.selected_coord().get_row() as usize];
-                let path = repo.git_repo
-                    .path()
-                    .parent()
-                    .unwrap();
-                std::process::Command::new("gnome-terminal")
-                    .arg(format!("--working-directory={}", path.display()))
-                    .output()
-                    .ok();
+                match config.open_actions.as_slice() {
+                    [] => tui.set_message("No open_actions in the config.", MessageLevel::Error),
+                    [action] => open_with(tui, repo, action),
+                    actions => {
+                        let lines = actions
+                            .iter()
+                            .map(|action| {
+                                let command = action.command_for(repo.path(), repo.name(), repo.current_branch());
+                                (format!("{}: {}", action.name, command.join(" ")), LineStyle::Plain)
+                            })
+                            .collect();
+                        let mut popup = Popup::new(&format!("Open {}", repo.name()), lines);
+                        popup.help = "enter: open  j/k: move  esc/q: close".to_string();
+                        state.popup = Some(popup);
+                        state.popup_actions = Some(PopupActions::Open(repo.path().to_owned()));
+                    }
+                }
             },
-            Column::Branches => {
-                let branch_index = tui.selected_coord().get_column() as usize - 2;
-                let repo = &repos[tui.selected_coord().get_row() as usize];
-                if repo.status().status_type() == StatusType::Clean {
-                    let branch = repo.branches()[branch_index].to_string();
-                    repo.checkout_branch(branch)?;
+            Column::Status => open_file_list(tui, &repos[tui.selected_coord().get_row() as usize], state),
+            Column::Branches => match selected_branch(tui, repos, state) {
+                Some((repo, BranchCell::Local(branch))) if config.auto_stash && branch != repo.current_branch() => {
+                    match repo.checkout_with_auto_stash(branch) {
+                        Ok(notes) if notes.is_empty() => tui.set_message(&format!("Checked out {}.", branch), MessageLevel::Info),
+                        Ok(notes) => tui.set_message(&format!("Checked out {}, {}.", branch, notes), MessageLevel::Info),
+                        Err(error) => tui.set_message(&format!("{}: {}", repo.name(), error), MessageLevel::Error),
+                    }
+                }
+                Some((repo, BranchCell::Local(branch))) if repo.status().status_type() == StatusType::Dirty
+                    && branch != repo.current_branch() =>
+                {
+                    let question = format!("{} has local changes, stash them first? y: stash  n: keep them  esc: cancel", repo.name());
+                    tui.set_message(&question, MessageLevel::Info);
+                    state.confirm = Some(Confirm::StashCheckout(repo.path().to_owned(), branch.to_string()));
+                }
+                Some((repo, BranchCell::Local(branch))) if branch != repo.current_branch() => {
+                    match repo.checkout_branch(branch) {
+                        Ok(()) => tui.set_message(&format!("Checked out {}.", branch), MessageLevel::Info),
+                        Err(error) => tui.set_message(&format!("{}: {}", repo.name(), error), MessageLevel::Error),
+                    }
                 }
+                Some((repo, BranchCell::Remote(branch))) => match repo.checkout_remote_branch(branch) {
+                    Ok(local) => tui.set_message(&format!("Checked out {} tracking {}.", local, branch), MessageLevel::Info),
+                    Err(error) => tui.set_message(&format!("{}: {}", repo.name(), error), MessageLevel::Error),
+                },
+                _ => {}
             },
-            _ => {},
         }
     }
     Ok(())
 }
 
-fn collect_repos(path: &Path, sort: &RepoSort) -> ReposResult<Vec<Repo>> {
-    let repos_in_dir = find_git_repos_in_dir(path)?;
-    let mut repo_threads = vec![];
-    for item in &repos_in_dir {
-        let p = item.clone();
-        repo_threads.push(
-            std::thread::spawn(move || Repo::new(&p).unwrap())
-        )
-    }
-    let mut repos: Vec<Repo> = repo_threads
-        .into_iter()
-        .map(|f| f.join())
-        .map(|f| f.unwrap())
+fn create_branches(
+    tui: &mut Tui,
+    repos: &[Repo],
+    state: &mut State,
+    paths: Vec<PathBuf>,
+    start_point: Option<String>,
+    branch: String,
+) {
+    let mut created = vec![];
+    let mut errors = vec![];
+    for repo in repos.iter().filter(|r| paths.iter().any(|p| p == r.path())) {
+        match repo.create_branch(&branch, start_point.as_deref()) {
+            Ok(()) => created.push(repo.path().to_owned()),
+            Err(error) => errors.push(format!("{}: {}", repo.name(), error)),
+        }
+    }
+    let mut message = String::new();
+    if !errors.is_empty() {
+        message.push_str(&format!("Not created in {}. ", errors.join(", ")));
+    }
+    let level = if errors.is_empty() { MessageLevel::Info } else { MessageLevel::Error };
+    if created.is_empty() {
+        tui.set_message(message.trim_end(), level);
+        return;
+    }
+    message.push_str(&format!("Created {} in {} repo(s), check it out? y/n", branch, created.len()));
+    tui.set_message(&message, level);
+    state.confirm = Some(Confirm::CheckoutBranch(created, branch));
+}
+
+// Every repo is switched back if a checkout fails.
+fn switch_branch(
+    tui: &mut Tui,
+    repos: &[Repo],
+    state: &mut State,
+    paths: Vec<PathBuf>,
+    branch: String,
+    create: bool,
+    auto_stash: bool,
+) {
+    let targets = repos.iter().filter(|r| paths.iter().any(|p| p == r.path())).collect::<Vec<_>>();
+    let options = SwitchOptions {
+        branch,
+        create,
+        rollback: true,
+        auto_stash,
+    };
+    let results = switch_repos(&targets, &options);
+    let failed = results.iter().any(|(_, state)| matches!(state, JobState::Failed(_)));
+    let level = if failed { MessageLevel::Error } else { MessageLevel::Info };
+    tui.set_message(&summary("switch", &results), level);
+    state.popup = Some(results_popup("switch", &results));
+}
+
+fn open_stash_list(tui: &mut Tui, repo: &Repo, state: &mut State) {
+    let stashes = match repo.stashes() {
+        Ok(stashes) => stashes,
+        Err(error) => {
+            tui.set_message(&error.to_string(), MessageLevel::Error);
+            return;
+        }
+    };
+    if stashes.is_empty() {
+        tui.set_message(&format!("No stashes in {}.", repo.name()), MessageLevel::Info);
+        return;
+    }
+    let lines = stashes
+        .iter()
+        .enumerate()
+        .map(|(index, message)| (format!("stash@{{{}}}: {}", index, message), LineStyle::Warning))
+        .collect();
+    let mut popup = Popup::new(&format!("{}: stashes", repo.name()), lines);
+    popup.help = "p: pop  a: apply  d: drop  j/k: move  esc/q: close".to_string();
+    state.popup = Some(popup);
+    state.popup_actions = Some(PopupActions::Stashes(repo.path().to_owned()));
+}
+
+// Changed files like in "git status --short", staged ones in green.
+fn open_file_list(tui: &mut Tui, repo: &Repo, state: &mut State) {
+    let files = match repo.file_statuses() {
+        Ok(files) => files,
+        Err(error) => {
+            tui.set_message(&error.to_string(), MessageLevel::Error);
+            return;
+        }
+    };
+    if files.is_empty() {
+        tui.set_message(&format!("No changes in {}.", repo.name()), MessageLevel::Info);
+        state.popup = None;
+        state.popup_actions = None;
+        return;
+    }
+    let lines = files
+        .iter()
+        .map(|file| {
+            let style = match (file.is_staged(), file.is_unstaged()) {
+                (true, false) => LineStyle::Good,
+                (_, true) if file.index == 'U' => LineStyle::Error,
+                _ => LineStyle::Warning,
+            };
+            (format!("{}{} {}", file.index, file.worktree, file.path), style)
+        })
+        .collect();
+    let mut popup = Popup::new(&format!("{}: {} changed file(s)", repo.name(), files.len()), lines);
+    popup.help = "enter: diff  s: stage  u: unstage  j/k: move  esc/q: close".to_string();
+    state.popup = Some(popup);
+    let paths = files.into_iter().map(|file| file.path).collect();
+    state.popup_actions = Some(PopupActions::Files(repo.path().to_owned(), paths));
+}
+
+// Staged hunks, then the unstaged ones, with coloured lines.
+fn open_diff(tui: &mut Tui, repo: &Repo, state: &mut State, file: &str) {
+    let diff = match repo.file_diff(file) {
+        Ok(diff) => diff,
+        Err(error) => {
+            tui.set_message(&error.to_string(), MessageLevel::Error);
+            return;
+        }
+    };
+    if diff.is_empty() {
+        tui.set_message(&format!("No text changes in {}.", file), MessageLevel::Info);
+        return;
+    }
+    let mut lines = vec![];
+    let mut hunks = vec![];
+    // The staged hunks come first.
+    let staged = diff.iter().filter(|hunk| hunk.staged).count();
+    for (index, hunk) in diff.iter().enumerate() {
+        let kind = if hunk.staged { "staged" } else { "unstaged" };
+        hunks.push(HunkLine {
+            line: lines.len(),
+            staged: hunk.staged,
+            index: if hunk.staged { index } else { index - staged },
+        });
+        lines.push((format!("{} {}", kind, hunk.header), LineStyle::Heading));
+        for line in &hunk.lines {
+            let style = match line.chars().next() {
+                Some('+') => LineStyle::Good,
+                Some('-') => LineStyle::Error,
+                Some(' ') => LineStyle::Plain,
+                _ => LineStyle::Dim,
+            };
+            lines.push((line.replace('\t', "    "), style));
+        }
+    }
+    let mut popup = Popup::new(&format!("{}: {}", repo.name(), file), lines);
+    popup.help = "n/N: next/previous hunk  s: stage hunk  j/k: move  esc/q: back".to_string();
+    state.popup = Some(popup);
+    state.popup_actions = Some(PopupActions::Diff(repo.path().to_owned(), file.to_string(), hunks));
+}
+
+// Lists the staged files and asks for the message.
+fn ask_commit(tui: &mut Tui, repo: &Repo, state: &mut State) {
+    let staged = match repo.file_statuses() {
+        Ok(files) => files.into_iter().filter(|f| f.is_staged()).collect::<Vec<_>>(),
+        Err(error) => {
+            tui.set_message(&error.to_string(), MessageLevel::Error);
+            return;
+        }
+    };
+    if staged.is_empty() {
+        tui.set_message(&format!("Nothing staged in {}.", repo.name()), MessageLevel::Info);
+        return;
+    }
+    let lines = staged
+        .iter()
+        .map(|file| (format!("{} {}", file.index, file.path), LineStyle::Good))
         .collect();
+    let title = format!("{}: commit {} staged file(s) on {}", repo.name(), staged.len(), repo.current_branch());
+    tui.clear_message();
+    state.popup = Some(Popup::new(&title, lines));
+    state.popup_actions = None;
+    state.prompt = Some((Prompt::new("Commit message"), PromptAction::Commit(repo.path().to_owned())));
+}
+
+// The command is left running, it's waited for in the background.
+fn open_with(tui: &mut Tui, repo: &Repo, action: &OpenAction) {
+    let command = action.command_for(repo.path(), repo.name(), repo.current_branch());
+    let (program, arguments) = match command.split_first() {
+        Some(split) => split,
+        None => {
+            tui.set_message(&format!("The {} open action has no command.", action.name), MessageLevel::Error);
+            return;
+        }
+    };
+    let child = std::process::Command::new(program)
+        .args(arguments)
+        .current_dir(repo.path())
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+            tui.set_message(&format!("Opened {} with {}.", repo.name(), action.name), MessageLevel::Info);
+        }
+        Err(error) => tui.set_message(&format!("{}: {}", program, error), MessageLevel::Error),
+    }
+}
+
+fn ask_delete_branch(tui: &mut Tui, repo: &Repo, state: &mut State, branch: &str) {
+    if branch == repo.current_branch() {
+        tui.set_message("Can't delete the current branch.", MessageLevel::Error);
+    } else {
+        tui.set_message(&format!("Delete {} in {}? y/n", branch, repo.name()), MessageLevel::Info);
+        state.confirm = Some(Confirm::DeleteBranch(repo.path().to_owned(), branch.to_string(), false));
+    }
+}
+
+// Lists the commits that would be lost first, and asks again.
+fn delete_branch(tui: &mut Tui, repo: &Repo, state: &mut State, branch: String, force: bool) {
+    if !force {
+        match repo.unmerged_commits(&branch) {
+            Ok(commits) if !commits.is_empty() => {
+                let title = format!("{}: {} commit(s) not on any other branch are lost", branch, commits.len());
+                let lines = commits.into_iter().map(|c| (c, LineStyle::Warning)).collect();
+                let mut popup = Popup::new(&title, lines);
+                popup.help = format!("Delete {} anyway? y/n", branch);
+                state.popup = Some(popup);
+                state.confirm = Some(Confirm::DeleteBranch(repo.path().to_owned(), branch, true));
+                return;
+            }
+            Err(error) => {
+                tui.set_message(&error.to_string(), MessageLevel::Error);
+                return;
+            }
+            _ => {}
+        }
+    }
+    match repo.delete_branch(&branch, force) {
+        Ok(()) => tui.set_message(&format!("Deleted {}.", branch), MessageLevel::Info),
+        Err(error) => tui.set_message(&error.to_string(), MessageLevel::Error),
+    }
+}
+
+fn start_push(tui: &mut Tui, state: &mut State, config: &Config, paths: Vec<PathBuf>, force_trunk: bool) {
+    let config = config.clone();
+    let job = BatchJob::start("push", paths, config.workers, move |path| {
+        push_repo(path, &config.repo_settings(path), force_trunk)
+    });
+    state.job = Some((job, JobReport::Message));
+    tui.set_message("Pushing...", MessageLevel::Info);
+}
+
+// The marked repos, or the selected one when none is marked.
+fn target_repos<'a>(tui: &Tui, repos: &'a [Repo], state: &State) -> Vec<&'a Repo> {
+    let marked = repos.iter().filter(|r| state.marked.contains(r.path())).collect::<Vec<_>>();
+    if marked.is_empty() {
+        vec![&repos[tui.selected_coord().get_row() as usize]]
+    } else {
+        marked
+    }
+}
+
+// Local branches, then the remote ones when they are shown.
+fn branch_cells<'a>(repo: &'a Repo, state: &State) -> Vec<BranchCell<'a>> {
+    let mut cells = repo.branches().iter().map(|b| BranchCell::Local(b)).collect::<Vec<_>>();
+    if state.show_remote_branches {
+        cells.extend(repo.remote_branches().iter().map(|b| BranchCell::Remote(b)));
+    }
+    cells
+}
+
+// The branch in the selected cell, when the cell is in the branches column.
+fn selected_branch<'a>(tui: &Tui, repos: &'a [Repo], state: &State) -> Option<(&'a Repo, BranchCell<'a>)> {
+    if tui.selected_coord().get_column().to_column() != Column::Branches {
+        return None;
+    }
+    let repo = &repos[tui.selected_coord().get_row() as usize];
+    let cell = *branch_cells(repo, state).get(tui.selected_coord().get_column() as usize - 2)?;
+    Some((repo, cell))
+}
+
+// The marked repos, or all of them when none is marked.
+fn batch_repos<'a>(repos: &'a [Repo], state: &State) -> Vec<&'a Repo> {
+    let marked = repos.iter().filter(|r| state.marked.contains(r.path())).collect::<Vec<_>>();
+    if marked.is_empty() {
+        repos.iter().collect()
+    } else {
+        marked
+    }
+}
+
+// Marks every repo, or none when all of them are marked.
+fn toggle_all_marks(repos: &[Repo], marked: &HashSet<PathBuf>) -> HashSet<PathBuf> {
+    if repos.iter().all(|r| marked.contains(r.path())) {
+        HashSet::new()
+    } else {
+        repos.iter().map(|r| r.path().to_owned()).collect()
+    }
+}
+
+// Cycles between marking the clean repos, the dirty ones and none.
+// Returns the new marks and the message about them.
+fn cycle_marks(repos: &[Repo], marked: &HashSet<PathBuf>) -> (HashSet<PathBuf>, String) {
+    let is_dirty = |repo: &Repo| repo.status().status_type() == StatusType::Dirty;
+    let with_status = |dirty: bool| -> HashSet<PathBuf> {
+        repos.iter().filter(|r| is_dirty(r) == dirty).map(|r| r.path().to_owned()).collect()
+    };
+    let (clean, dirty) = (with_status(false), with_status(true));
+    if !marked.is_empty() && *marked == dirty {
+        (HashSet::new(), "Cleared the marks.".to_string())
+    } else if !marked.is_empty() && *marked == clean {
+        let message = format!("Marked {} dirty repo(s).", dirty.len());
+        (dirty, message)
+    } else {
+        let message = format!("Marked {} clean repo(s).", clean.len());
+        (clean, message)
+    }
+}
+
+// Upper case letters and symbols can come with the shift modifier, so only the key code is compared.
+fn is_char(event: &Event, c: char) -> bool {
+    matches!(event, Event::Key(key) if key.code == KeyCode::Char(c))
+}
+
+fn can_start_job(tui: &mut Tui, state: &State) -> bool {
+    if state.job.is_some() {
+        tui.set_message("Wait for the running job to finish.", MessageLevel::Error);
+        return false;
+    }
+    true
+}
+
+// Repo paths with the index of the group they belong to.
+// A repo is only listed in the first group it's found in.
+fn find_repos_in_groups(sources: &[RepoSource]) -> ReposResult<Vec<(usize, PathBuf)>> {
+    let mut seen = std::collections::HashSet::new();
+    let mut paths = vec![];
+    for (group, source) in sources.iter().enumerate() {
+        for item in source.find_repos()? {
+            if seen.insert(item.clone()) {
+                paths.push((group, item));
+            }
+        }
+    }
+    Ok(paths)
+}
+
+pub fn find_repo_paths(sources: &[RepoSource]) -> ReposResult<Vec<PathBuf>> {
+    Ok(find_repos_in_groups(sources)?.into_iter().map(|(_, path)| path).collect())
+}
+
+pub fn collect_repos(sources: &[RepoSource], config: &Config, sort: &RepoSort) -> ReposResult<Vec<Repo>> {
+    let mut repo_threads = vec![];
+    for (group, item) in find_repos_in_groups(sources)? {
+        let settings = config.repo_settings(&item);
+        // The error is sent as text, boxed errors can't leave the thread.
+        repo_threads.push(std::thread::spawn(move || {
+            Repo::new(&item, &settings)
+                .map(|repo| repo.with_group(group))
+                .map_err(|error| format!(r#"Could not read repo "{}": {}"#, item.display(), error))
+        }))
+    }
+    let mut repos = vec![];
+    for thread in repo_threads {
+        let repo = thread.join().map_err(|_| Error::other("Reading a repo panicked."))?;
+        repos.push(repo.map_err(Error::other)?);
+    }
     match sort {
-        RepoSort::Alpha => repos.sort_by_key(|r| r.name().to_string()),
-        RepoSort::Status => repos.sort_by_key(|r| r.status().to_string()),
-        RepoSort::CurrentBranch => repos.sort_by_key(|r| r.current_branch().to_string()),
+        RepoSort::Alpha => repos.sort_by_key(|r| (r.group(), r.name().to_string())),
+        RepoSort::Status => repos.sort_by_key(|r| (r.group(), r.status().to_string())),
+        RepoSort::CurrentBranch => repos.sort_by_key(|r| (r.group(), r.current_branch().to_string())),
     }
     if *sort == RepoSort::CurrentBranch {
         repos.iter_mut().for_each(|repo| repo.set_current_branch_as_first());
@@ -153,3 +1154,35 @@ fn collect_repos(path: &Path, sort: &RepoSort) -> ReposResult<Vec<Repo>> {
     }
     Ok(repos)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutils::*;
+
+    #[test]
+    fn cycling_the_marks() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = ["api", "web", "dirty"].map(|name| root.path().join(name));
+        for path in &paths {
+            init_repo(path);
+        }
+        std::fs::write(paths[2].join("README"), "changed").unwrap();
+        let repos = paths.iter().map(|path| open_repo(path)).collect::<Vec<_>>();
+        let set = |paths: &[PathBuf]| paths.iter().cloned().collect::<HashSet<_>>();
+
+        let (marked, message) = cycle_marks(&repos, &HashSet::new());
+        assert_eq!((marked.clone(), message.as_str()), (set(&paths[..2]), "Marked 2 clean repo(s)."));
+        let (marked, message) = cycle_marks(&repos, &marked);
+        assert_eq!((marked.clone(), message.as_str()), (set(&paths[2..]), "Marked 1 dirty repo(s)."));
+        let (marked, message) = cycle_marks(&repos, &marked);
+        assert_eq!((marked, message.as_str()), (HashSet::new(), "Cleared the marks."));
+        // Marks set by hand start the cycle again.
+        let (marked, _) = cycle_marks(&repos, &set(&paths[1..2]));
+        assert_eq!(marked, set(&paths[..2]));
+
+        let all = toggle_all_marks(&repos, &set(&paths[1..2]));
+        assert_eq!(all, set(&paths));
+        assert!(toggle_all_marks(&repos, &all).is_empty());
+    }
+}
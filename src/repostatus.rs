@@ -17,10 +17,14 @@ is_ignored                  IGNORED             = raw::GIT_STATUS_IGNORED as u32
 is_conflicted               CONFLICTED          = raw::GIT_STATUS_CONFLICTED as u32;
 */
 
-#[derive(PartialEq)]
+const AHEAD_BEHIND_WIDTH: usize = 8;
+
+#[derive(PartialEq, Debug)]
 pub enum StatusType {
     Clean,
     Dirty,
+    // Clean, but the branch is ahead or behind its upstream.
+    Unsynced,
 }
 
 pub struct Status {
@@ -38,6 +42,9 @@ pub struct Status {
     wt_renamed: bool,
     ignored: bool,
     conflicted: bool,
+    // Commits ahead and behind the upstream of the current branch.
+    // None without an upstream.
+    ahead_behind: Option<(usize, usize)>,
 }
 
 impl Status {
@@ -56,9 +63,19 @@ impl Status {
             wt_renamed: false,
             ignored: false,
             conflicted: false,
+            ahead_behind: None,
         }
     }
 
+    pub fn set_ahead_behind(mut self, ahead_behind: Option<(usize, usize)>) -> Self {
+        self.ahead_behind = ahead_behind;
+        self
+    }
+
+    pub fn ahead_behind(&self) -> Option<(usize, usize)> {
+        self.ahead_behind
+    }
+
     pub fn set_from_vec(mut self, statuses: Vec<git2::Status>) -> Self {
         for item in statuses {
             if item.is_index_new() { self.index_new = true };
@@ -77,6 +94,24 @@ impl Status {
         self
     }
 
+    // Flag names with their values, in the order of the status string.
+    pub fn flags(&self) -> [(&'static str, bool); 12] {
+        [
+            ("index_new", self.index_new),
+            ("index_modified", self.index_modified),
+            ("index_deleted", self.index_deleted),
+            ("index_renamed", self.index_renamed),
+            ("index_typechange", self.index_typechange),
+            ("wt_new", self.wt_new),
+            ("wt_modified", self.wt_modified),
+            ("wt_deleted", self.wt_deleted),
+            ("wt_typechange", self.wt_typechange),
+            ("wt_renamed", self.wt_renamed),
+            ("ignored", self.ignored),
+            ("conflicted", self.conflicted),
+        ]
+    }
+
     pub fn status_type(&self) -> StatusType {
         if self.index_new { return StatusType::Dirty };
         if self.index_modified { return StatusType::Dirty };
@@ -90,7 +125,10 @@ impl Status {
         if self.wt_renamed { return StatusType::Dirty };
         if self.ignored { return StatusType::Dirty };
         if self.conflicted { return StatusType::Dirty };
-        StatusType::Clean
+        match self.ahead_behind {
+            Some((0, 0)) | None => StatusType::Clean,
+            Some(_) => StatusType::Unsynced,
+        }
     }
 }
 
@@ -111,6 +149,35 @@ impl std::fmt::Display for Status {
         if self.conflicted { stat_chars[11] = 'c' };
         let stat_chars = stat_chars.into_iter().map(|f| f as u8).collect();
         let stat_chars = String::from_utf8(stat_chars).unwrap();
-        write!(f, "[{}]", stat_chars)
+        write!(f, "[{}]{:<w$}", stat_chars, format_ahead_behind(self.ahead_behind), w = AHEAD_BEHIND_WIDTH)
+    }
+}
+
+// Like " +2-1", empty when there's nothing to push or pull.
+pub fn format_ahead_behind(ahead_behind: Option<(usize, usize)>) -> String {
+    match ahead_behind {
+        Some((0, 0)) | None => String::new(),
+        Some((ahead, 0)) => format!(" +{}", ahead),
+        Some((0, behind)) => format!(" -{}", behind),
+        Some((ahead, behind)) => format!(" +{}-{}", ahead, behind),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn showing_ahead_behind() {
+        let status = Status::new().set_ahead_behind(Some((2, 1)));
+        assert_eq!(status.to_string(), "[            ] +2-1   ");
+        assert_eq!(status.status_type(), StatusType::Unsynced);
+        let status = Status::new().set_ahead_behind(Some((0, 0)));
+        assert_eq!(status.to_string().trim_end(), "[            ]");
+        assert_eq!(status.status_type(), StatusType::Clean);
+        let status = Status::new()
+            .set_from_vec(vec![git2::Status::WT_MODIFIED])
+            .set_ahead_behind(Some((0, 3)));
+        assert_eq!(status.status_type(), StatusType::Dirty);
     }
 }
@@ -0,0 +1,260 @@
+use crate::prelude::*;
+
+pub struct SwitchOptions {
+    pub branch: String,
+    // Create the branch from HEAD in the repos that don't have it.
+    pub create: bool,
+    // Switch every repo back to its previous branch when a checkout fails.
+    pub rollback: bool,
+    // Stash the local changes instead of skipping dirty repos, see `Repo::checkout_with_auto_stash`.
+    pub auto_stash: bool,
+}
+
+// Checks out the branch in the repos one by one, for working on a task
+// across repos that depend on each other.
+pub fn switch_repos(repos: &[&Repo], options: &SwitchOptions) -> Vec<(PathBuf, JobState)> {
+    let branch = &options.branch;
+    let mut results = vec![];
+    // Index of the result, and whether the branch was created.
+    let mut switched = vec![];
+    let mut failed = false;
+    for repo in repos {
+        if failed && options.rollback {
+            results.push((repo.path().to_owned(), JobState::Skipped("not tried, rolled back".to_string())));
+            continue;
+        }
+        let exists = repo.branches().contains(branch);
+        let remote = remote_branch(repo, branch);
+        let state = if !repo.kind().can_checkout() {
+            JobState::Skipped("bare repo".to_string())
+        } else if repo.current_branch() == branch {
+            JobState::Done(format!("already on {}", branch))
+        } else if !exists && remote.is_none() && !options.create {
+            JobState::Skipped(format!("no branch {}", branch))
+        } else if repo.status().status_type() == StatusType::Dirty && !options.auto_stash {
+            JobState::Skipped("dirty".to_string())
+        } else {
+            let create = match (exists, &remote) {
+                (true, _) => Create::No,
+                (false, Some(remote)) => Create::Tracking(remote),
+                (false, None) => Create::FromHead,
+            };
+            match checkout(repo, branch, &create, options.auto_stash) {
+                Ok(notes) => {
+                    switched.push((results.len(), !exists));
+                    let created = match create {
+                        Create::No => String::new(),
+                        Create::Tracking(remote) => format!("created from {}, ", remote),
+                        Create::FromHead => "created, ".to_string(),
+                    };
+                    let notes = if notes.is_empty() { notes } else { format!(", {}", notes) };
+                    JobState::Done(format!("{}switched from {}{}", created, repo.current_branch(), notes))
+                }
+                Err(error) => {
+                    failed = true;
+                    JobState::Failed(error.to_string())
+                }
+            }
+        };
+        results.push((repo.path().to_owned(), state));
+    }
+
+    if failed && options.rollback {
+        for (index, created) in switched {
+            let repo = repos[index];
+            let previous = repo.current_branch();
+            // The repo is read again, it's on the new branch now.
+            let rolled_back = repo.reload().and_then(|current| {
+                checkout(&current, previous, &Create::No, options.auto_stash)?;
+                match created {
+                    true => repo.delete_branch(branch, true),
+                    false => Ok(()),
+                }
+            });
+            results[index].1 = match rolled_back {
+                Ok(()) => JobState::Skipped(format!("rolled back to {}", previous)),
+                Err(error) => JobState::Failed(format!("rollback failed: {}", error)),
+            };
+        }
+    }
+    results
+}
+
+// The remote branch with the name, when the repo has no local one.
+pub fn remote_branch(repo: &Repo, branch: &str) -> Option<String> {
+    let remote = format!("origin/{}", branch);
+    repo.remote_branches().contains(&remote).then_some(remote)
+}
+
+// How the branch is created in the repos that don't have it.
+enum Create<'a> {
+    No,
+    // Tracking the remote branch with the same name.
+    Tracking(&'a str),
+    FromHead,
+}
+
+// Returns what was done with the stashes.
+fn checkout(repo: &Repo, branch: &str, create: &Create, auto_stash: bool) -> ReposResult<String> {
+    match create {
+        Create::No => {}
+        // Like `Repo::checkout_remote_branch`, but the checkout can stash.
+        Create::Tracking(remote) => {
+            repo.create_tracking_branch(remote)?;
+        }
+        Create::FromHead => repo.create_branch(branch, None)?,
+    }
+    let result = match auto_stash {
+        true => repo.checkout_with_auto_stash(branch),
+        false => repo.checkout_branch(branch).map(|_| String::new()).map_err(Into::into),
+    };
+    if result.is_err() && !matches!(create, Create::No) {
+        repo.delete_branch(branch, true)?;
+    }
+    result
+}
+
+pub fn switch(args: &Args, options: &SwitchOptions) -> ReposResult<()> {
+    let repos = collect_repos(&args.sources, &args.config, &RepoSort::Alpha)?;
+    let results = switch_repos(&repos.iter().collect::<Vec<_>>(), options);
+    results.iter().for_each(|(path, state)| print_job_state(path, state));
+    println!("{}", summary("switch", &results));
+    let failed = results
+        .iter()
+        .filter(|(_, state)| matches!(state, JobState::Failed(_)))
+        .count();
+    if failed > 0 {
+        return Err(Box::new(Error::other(format!("{} failed.", failed))));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutils::*;
+
+    fn options(branch: &str, create: bool) -> SwitchOptions {
+        SwitchOptions {
+            branch: branch.to_string(),
+            create,
+            rollback: true,
+            auto_stash: false,
+        }
+    }
+
+    #[test]
+    fn switching_a_branch_across_repos() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = ["api", "web", "dirty", "on-it"].map(|name| root.path().join(name));
+        for path in &paths {
+            init_repo(path);
+        }
+        open_repo(&paths[0]).create_branch("task", None).unwrap();
+        open_repo(&paths[2]).create_branch("task", None).unwrap();
+        std::fs::write(paths[2].join("README"), "changed").unwrap();
+        open_repo(&paths[3]).create_branch("task", None).unwrap();
+        open_repo(&paths[3]).checkout_branch("task").unwrap();
+
+        let repos = paths.iter().map(|path| open_repo(path)).collect::<Vec<_>>();
+        let states = switch_repos(&repos.iter().collect::<Vec<_>>(), &options("task", false))
+            .into_iter()
+            .map(|(_, state)| state)
+            .collect::<Vec<_>>();
+        assert_eq!(states[0], JobState::Done("switched from master".to_string()));
+        assert_eq!(states[1], JobState::Skipped("no branch task".to_string()));
+        assert_eq!(states[2], JobState::Skipped("dirty".to_string()));
+        assert_eq!(states[3], JobState::Done("already on task".to_string()));
+        assert_eq!(open_repo(&paths[0]).current_branch(), "task");
+        assert_eq!(open_repo(&paths[2]).current_branch(), "master");
+
+        let results = switch_repos(&[&open_repo(&paths[1])], &options("task", true));
+        assert_eq!(results[0].1, JobState::Done("created, switched from master".to_string()));
+        assert_eq!(open_repo(&paths[1]).current_branch(), "task");
+
+        let auto_stash = SwitchOptions {
+            auto_stash: true,
+            ..options("task", false)
+        };
+        let results = switch_repos(&[&open_repo(&paths[2])], &auto_stash);
+        assert_eq!(results[0].1, JobState::Done("switched from master, stashed the changes of master".to_string()));
+        assert_eq!(open_repo(&paths[2]).stash_count(), 1);
+    }
+
+    #[test]
+    fn switching_to_a_remote_branch() {
+        let root = tempfile::tempdir().unwrap();
+        let (remote, seed) = init_remote(root.path());
+        open_repo(&root.path().join("seed")).create_branch("task", None).unwrap();
+        push(&seed, "task");
+        let paths = ["api", "locked"].map(|name| root.path().join(name));
+        for path in &paths {
+            clone_repo(&remote, path);
+        }
+
+        let api = open_repo(&paths[0]);
+        assert_eq!(remote_branch(&api, "task"), Some("origin/task".to_string()));
+        let results = switch_repos(&[&api], &options("task", false));
+        assert_eq!(results[0].1, JobState::Done("created from origin/task, switched from master".to_string()));
+        let api = Repository::open(&paths[0]).unwrap();
+        let upstream = api.find_branch("task", BranchType::Local).unwrap().upstream().unwrap();
+        assert_eq!(upstream.name().unwrap(), Some("origin/task"));
+
+        // The created branch is deleted again when the checkout fails.
+        std::fs::write(paths[1].join(".git/index.lock"), "").unwrap();
+        let results = switch_repos(&[&open_repo(&paths[1])], &options("task", false));
+        assert!(matches!(results[0].1, JobState::Failed(_)));
+        assert_eq!(open_repo(&paths[1]).branches(), &vec!["master".to_string()]);
+    }
+
+    #[test]
+    fn rolling_back_when_a_checkout_fails() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = ["api", "locked", "web"].map(|name| root.path().join(name));
+        for path in &paths {
+            init_repo(path);
+        }
+        open_repo(&paths[1]).create_branch("task", None).unwrap();
+        std::fs::write(paths[1].join(".git/index.lock"), "").unwrap();
+
+        let repos = paths.iter().map(|path| open_repo(path)).collect::<Vec<_>>();
+        let results = switch_repos(&repos.iter().collect::<Vec<_>>(), &options("task", true));
+        assert_eq!(results[0].1, JobState::Skipped("rolled back to master".to_string()));
+        assert!(matches!(results[1].1, JobState::Failed(_)));
+        assert_eq!(results[2].1, JobState::Skipped("not tried, rolled back".to_string()));
+        let api = open_repo(&paths[0]);
+        assert_eq!(api.current_branch(), "master");
+        assert_eq!(api.branches(), &vec!["master".to_string()]);
+    }
+
+    #[test]
+    fn rolling_back_when_a_checkout_conflicts() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = ["api", "conflict", "web"].map(|name| root.path().join(name));
+        for path in &paths {
+            init_repo(path);
+            open_repo(path).create_branch("task", None).unwrap();
+        }
+        let conflict = Repository::open(&paths[1]).unwrap();
+        conflict.set_head("refs/heads/task").unwrap();
+        commit_file(&conflict, "notes", "on task\n", "Notes.");
+        conflict.set_head("refs/heads/master").unwrap();
+        conflict.checkout_head(Some(git2::build::CheckoutBuilder::new().force())).unwrap();
+
+        // The untracked file is written after the repos are read, while they are clean,
+        // it would be overwritten by the one on the task branch.
+        let repos = paths.iter().map(|path| open_repo(path)).collect::<Vec<_>>();
+        std::fs::write(paths[1].join("notes"), "untracked\n").unwrap();
+        let results = switch_repos(&repos.iter().collect::<Vec<_>>(), &options("task", false));
+        assert_eq!(results[0].1, JobState::Skipped("rolled back to master".to_string()));
+        match &results[1].1 {
+            JobState::Failed(error) => assert!(error.contains("notes"), "{}", error),
+            state => panic!("the checkout didn't fail: {:?}", state),
+        }
+        assert_eq!(results[2].1, JobState::Skipped("not tried, rolled back".to_string()));
+        for path in &paths {
+            assert_eq!(open_repo(path).current_branch(), "master");
+        }
+        assert_eq!(std::fs::read_to_string(paths[1].join("notes")).unwrap(), "untracked\n");
+    }
+}
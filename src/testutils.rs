@@ -0,0 +1,73 @@
+// Helpers to set up throwaway repos for tests.
+
+use crate::prelude::*;
+
+pub fn init_repo(path: &Path) -> Repository {
+    let mut options = git2::RepositoryInitOptions::new();
+    options.initial_head("master");
+    let repo = Repository::init_opts(path, &options).unwrap();
+    {
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Test User").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+    }
+    commit_file(&repo, "README", "init\n", "Init.");
+    repo
+}
+
+pub fn open_repo(path: &Path) -> Repo {
+    Repo::new(path, &Config::default().repo_settings(path)).unwrap()
+}
+
+pub fn init_gitfile_repo(workdir: &Path, gitdir: &Path) -> Repository {
+    let mut options = git2::RepositoryInitOptions::new();
+    options.workdir_path(workdir);
+    Repository::init_opts(gitdir, &options).unwrap()
+}
+
+pub fn commit_file(repo: &Repository, file: &str, content: &str, message: &str) -> git2::Oid {
+    let workdir = repo.workdir().unwrap();
+    std::fs::write(workdir.join(file), content).unwrap();
+    let mut index = repo.index().unwrap();
+    index.add_path(Path::new(file)).unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = git2::Signature::now("Test User", "test@example.com").unwrap();
+    let parent = repo.head().ok().and_then(|h| h.peel_to_commit().ok());
+    let parents = parent.iter().collect::<Vec<_>>();
+    repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents)
+        .unwrap()
+}
+
+// A bare repo to be used as "origin", and a clone of it with a commit on master.
+pub fn init_remote(root: &Path) -> (PathBuf, Repository) {
+    let remote = root.join("remote.git");
+    let mut options = git2::RepositoryInitOptions::new();
+    options.bare(true).initial_head("master");
+    Repository::init_opts(&remote, &options).unwrap();
+    let seed = init_repo(&root.join("seed"));
+    seed.remote("origin", remote.to_str().unwrap()).unwrap();
+    push(&seed, "master");
+    seed.find_branch("master", BranchType::Local)
+        .unwrap()
+        .set_upstream(Some("origin/master"))
+        .unwrap();
+    (remote, seed)
+}
+
+pub fn clone_repo(remote: &Path, path: &Path) -> Repository {
+    let repo = Repository::clone(remote.to_str().unwrap(), path).unwrap();
+    {
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Test User").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+    }
+    repo
+}
+
+pub fn push(repo: &Repository, branch: &str) {
+    repo.find_remote("origin")
+        .unwrap()
+        .push(&[format!("refs/heads/{0}:refs/heads/{0}", branch)], None)
+        .unwrap();
+}
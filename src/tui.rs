@@ -1,5 +1,7 @@
 const COL_OFFSET: u16 = 0;
 const ROW_OFFSET: u16 = 1;
+// Title and help lines of the popups.
+const POPUP_EXTRA_LINES: u16 = 2;
 
 use crate::prelude::*;
 
@@ -89,13 +91,64 @@ pub enum CellStyle {
     Default,
     CurrentBranch,
     Branch,
-    CleanMaster,
-    DirtyMaster,
+    RemoteBranch,
+    CleanTrunk,
+    DirtyTrunk,
     CleanBranch,
     DirtyBranch,
     Info,
 }
 
+#[derive(PartialEq)]
+pub enum MessageLevel {
+    Info,
+    Error,
+}
+
+// Text typed in the message line, like the name of a new branch.
+pub struct Prompt {
+    pub label: String,
+    pub input: String,
+}
+
+pub enum PromptEvent {
+    Editing,
+    Submitted(String),
+    Cancelled,
+}
+
+impl Prompt {
+    pub fn new(label: &str) -> Self {
+        Self {
+            label: label.to_string(),
+            input: String::new(),
+        }
+    }
+
+    // Starts with text to edit, like the current name of a branch.
+    pub fn with_input(mut self, input: &str) -> Self {
+        self.input = input.to_string();
+        self
+    }
+
+    pub fn on_key(&mut self, event: &Event) -> PromptEvent {
+        let code = match event {
+            Event::Key(key) => key.code,
+            _ => return PromptEvent::Editing,
+        };
+        match code {
+            KeyCode::Char(c) => self.input.push(c),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Enter => return PromptEvent::Submitted(self.input.trim().to_string()),
+            KeyCode::Esc => return PromptEvent::Cancelled,
+            _ => {}
+        }
+        PromptEvent::Editing
+    }
+}
+
 pub struct Tui {
     // row that's being currently printed in the loop.
     // this is checked against the selected row.
@@ -106,21 +159,29 @@ pub struct Tui {
     wip_column_coord: u16,
     column_counts: Vec<u16>,
     row_count: u16,
+    // Lines between the rows that can't be selected, like group headers.
+    header_count: u16,
     buff: std::io::BufWriter<std::io::Stdout>,
     previous_column_width: u16,
+    repo_name_width: u16,
+    // Shown under the status line until it's replaced or cleared.
+    message: Option<(String, MessageLevel)>,
     pub cell_style: CellStyle,
 }
 
 impl Tui {
-    pub fn new() -> Self {
+    pub fn new(config: &Config) -> Self {
         Self {
             wip_cell: CellCoord::new(),
             selected_cell: CellCoord::new(),
             wip_column_coord: 0,
             column_counts: vec![0],
             row_count: 0,
+            header_count: 0,
             buff: std::io::BufWriter::new(stdout()),
             previous_column_width: 0,
+            repo_name_width: config.repo_name_width,
+            message: None,
             cell_style: CellStyle::Default,
         }
     }
@@ -135,6 +196,7 @@ impl Tui {
             .queue(Clear(ClearType::All))?
             .queue(MoveTo(self.wip_cell.get_row(), self.wip_cell.get_column()))?;
         self.row_count = 0;
+        self.header_count = 0;
         self.column_counts = vec![0];
         self.buff.queue(crossterm::cursor::MoveToNextLine(ROW_OFFSET))?;
         Ok(())
@@ -143,12 +205,12 @@ impl Tui {
     fn calc_wip_column_coord<'a>(&self, text: &'a str) -> (u16, &'a str) {
         match self.wip_cell.get_column().to_column() {
             Column::Name => (COL_OFFSET, text),
-            Column::Status => (self.wip_column_coord + REPO_NAME_WIDTH + COL_OFFSET, text),
+            Column::Status => (self.wip_column_coord + self.repo_name_width + COL_OFFSET, text),
             Column::Branches => {
                 let (width, _) = terminal::size().unwrap();
-                let test_column_coord = self.wip_column_coord + self.previous_column_width as u16;
+                let test_column_coord = self.wip_column_coord + self.previous_column_width;
                 let test_column_coord = test_column_coord + COL_OFFSET;
-                if test_column_coord > width - (text.len() as u16) {
+                if test_column_coord > width.saturating_sub(text.chars().count() as u16) {
                     (width - 5, " >>>")
                 } else {
                     (test_column_coord, text)
@@ -159,7 +221,8 @@ impl Tui {
 
     pub fn print(&mut self, mut text: &str) -> ReposResult<()> {
         (self.wip_column_coord, text) = self.calc_wip_column_coord(text);
-        self.previous_column_width = text.len() as u16;
+        // Columns are measured in chars, names like "José" have more bytes.
+        self.previous_column_width = text.chars().count() as u16;
         let cell_gap = 1;
         self.wip_column_coord += cell_gap;
         self.buff.queue(MoveToColumn(self.wip_column_coord))?;
@@ -177,6 +240,18 @@ impl Tui {
         Ok(())
     }
 
+    // In the gap before the name, it's not a cell.
+    pub fn print_marker(&mut self, marked: bool) -> ReposResult<()> {
+        if marked {
+            self.buff
+                .queue(MoveToColumn(0))?
+                .queue(SetForegroundColor(Color::Magenta))?
+                .queue(Print("*"))?
+                .queue(ResetColor)?;
+        }
+        Ok(())
+    }
+
     pub fn set_cell_style(&mut self, style: CellStyle) {
         self.cell_style = style;
     }
@@ -195,12 +270,16 @@ impl Tui {
                 self.buff.queue(SetForegroundColor(Color::Rgb { r: 95, g: 85, b: 80 }))?;
                 self.cell_style = CellStyle::Default;
             }
-            CellStyle::CleanMaster => {
+            CellStyle::RemoteBranch => {
+                self.buff.queue(SetForegroundColor(Color::Rgb { r: 60, g: 55, b: 50 }))?;
+                self.cell_style = CellStyle::Default;
+            }
+            CellStyle::CleanTrunk => {
                 if self.wip_cell.get_column() < 2 {
                     self.buff.queue(SetForegroundColor(Color::Green))?;
                 }
             }
-            CellStyle::DirtyMaster => {
+            CellStyle::DirtyTrunk => {
                 if self.wip_cell.get_column() < 2 {
                     self.buff.queue(SetForegroundColor(Color::Rgb { r: 255, g: 205, b: 0 }))?;
                 }
@@ -239,6 +318,18 @@ impl Tui {
         Ok(())
     }
 
+    pub fn print_header(&mut self, text: &str) -> ReposResult<()> {
+        self.set_cell_style(CellStyle::Info);
+        self.apply_cell_style()?;
+        self.buff
+            .queue(MoveToColumn(0))?
+            .queue(Print(text))?
+            .queue(ResetColor)?
+            .queue(MoveToNextLine(1))?;
+        self.header_count += 1;
+        Ok(())
+    }
+
     pub fn go(&mut self, direction: Direction) {
         match direction {
             Direction::Up => {
@@ -271,15 +362,120 @@ impl Tui {
         }
     }
 
-    pub fn print_status(&mut self, repo_name: &str, current_branch: &str, selected_cell_branch: &str) -> ReposResult<()> {
+    pub fn print_status(
+        &mut self,
+        repo_name: &str,
+        current_branch: &str,
+        selected_cell_branch: &str,
+        marked_count: usize,
+    ) -> ReposResult<()> {
         self.buff
-            .queue(MoveTo(0, self.row_count + 1))?
+            .queue(MoveTo(0, self.row_count + self.header_count + ROW_OFFSET))?
             .queue(Print(repo_name))?
-            .queue(MoveToColumn(REPO_NAME_WIDTH + COL_OFFSET + 2))?
+            .queue(MoveToColumn(self.repo_name_width + COL_OFFSET + 2))?
             .queue(Print("|"))?
             .queue(Print(current_branch))?
             .queue(Print("|"))?
             .queue(Print(selected_cell_branch))?;
+        if marked_count > 0 {
+            self.buff.queue(Print(format!("|{} marked", marked_count)))?;
+        }
+        self.print_message()?;
+        Ok(())
+    }
+
+    pub fn set_message(&mut self, text: &str, level: MessageLevel) {
+        self.message = Some((text.to_string(), level));
+    }
+
+    pub fn clear_message(&mut self) {
+        self.message = None;
+    }
+
+    fn print_message(&mut self) -> ReposResult<()> {
+        if let Some((text, level)) = &self.message {
+            let color = match level {
+                MessageLevel::Info => Color::Rgb { r: 150, g: 150, b: 150 },
+                MessageLevel::Error => Color::Rgb { r: 255, g: 0, b: 0 },
+            };
+            self.buff
+                .queue(MoveTo(0, self.row_count + self.header_count + ROW_OFFSET + 1))?
+                .queue(SetForegroundColor(color))?
+                .queue(Print(text))?
+                .queue(ResetColor)?;
+        }
+        Ok(())
+    }
+
+    // Drawn over the message line.
+    pub fn print_prompt(&mut self, prompt: &Prompt) -> ReposResult<()> {
+        self.print_prompt_at(self.row_count + self.header_count + ROW_OFFSET + 1, prompt)
+    }
+
+    // Drawn over the help line of the popup.
+    pub fn print_popup_prompt(&mut self, prompt: &Prompt) -> ReposResult<()> {
+        let (_, height) = terminal::size()?;
+        self.print_prompt_at(height.saturating_sub(1), prompt)
+    }
+
+    fn print_prompt_at(&mut self, row: u16, prompt: &Prompt) -> ReposResult<()> {
+        self.buff
+            .queue(MoveTo(0, row))?
+            .queue(Clear(ClearType::UntilNewLine))?
+            .queue(Print(format!("{}: {}", prompt.label, prompt.input)))?
+            .queue(SetBackgroundColor(Color::Rgb { r: 90, g: 15, b: 0 }))?
+            .queue(Print(" "))?
+            .queue(ResetColor)?;
+        Ok(())
+    }
+
+    // Under the message line, as many lines as fit.
+    pub fn print_log(&mut self, title: &str, lines: &[String]) -> ReposResult<()> {
+        let (width, height) = terminal::size()?;
+        let width = width as usize;
+        let first_row = self.row_count + self.header_count + ROW_OFFSET + 3;
+        self.buff
+            .queue(MoveTo(0, first_row))?
+            .queue(SetForegroundColor(Color::Rgb { r: 80, g: 80, b: 80 }))?
+            .queue(Print(limit_text(title, &width)))?
+            .queue(ResetColor)?;
+        for (index, line) in lines.iter().enumerate() {
+            let row = first_row + 1 + index as u16;
+            if row >= height {
+                break;
+            }
+            self.buff.queue(MoveTo(0, row))?.queue(Print(limit_text(line, &width)))?;
+        }
+        Ok(())
+    }
+
+    pub fn print_popup(&mut self, popup: &Popup) -> ReposResult<()> {
+        let (width, height) = terminal::size()?;
+        let width = width as usize;
+        let visible_lines = height.saturating_sub(POPUP_EXTRA_LINES) as usize;
+        let first_line = popup.first_visible_line(visible_lines);
+        self.buff
+            .queue(MoveTo(0, 0))?
+            .queue(SetForegroundColor(Color::Rgb { r: 80, g: 80, b: 80 }))?
+            .queue(Print(limit_text(&popup.title, &width)))?
+            .queue(ResetColor)?;
+        let lines = popup.lines.iter().enumerate().skip(first_line).take(visible_lines);
+        for (screen_row, (index, (text, style))) in lines.enumerate() {
+            self.buff
+                .queue(MoveTo(0, screen_row as u16 + 1))?
+                .queue(SetForegroundColor(style.color()))?;
+            if index == popup.selected() {
+                self.buff.queue(SetBackgroundColor(Color::Rgb { r: 90, g: 15, b: 0 }))?;
+            }
+            self.buff
+                .queue(Print(limit_text(text, &width)))?
+                .queue(ResetColor)?;
+        }
+        self.buff
+            .queue(MoveTo(0, height.saturating_sub(1)))?
+            .queue(SetForegroundColor(Color::Rgb { r: 80, g: 80, b: 80 }))?
+            .queue(Print(limit_text(&popup.help, &width)))?
+            .queue(ResetColor)?;
         Ok(())
     }
 
@@ -292,3 +488,24 @@ impl Tui {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(code: KeyCode) -> Event {
+        Event::Key(code.into())
+    }
+
+    #[test]
+    fn typing_in_a_prompt() {
+        let mut prompt = Prompt::new("New branch");
+        for c in "feature-xy".chars() {
+            assert!(matches!(prompt.on_key(&key(KeyCode::Char(c))), PromptEvent::Editing));
+        }
+        prompt.on_key(&key(KeyCode::Backspace));
+        assert_eq!(prompt.input, "feature-x");
+        assert!(matches!(prompt.on_key(&key(KeyCode::Enter)), PromptEvent::Submitted(text) if text == "feature-x"));
+        assert!(matches!(prompt.on_key(&key(KeyCode::Esc)), PromptEvent::Cancelled));
+    }
+}
@@ -2,75 +2,581 @@ use crate::prelude::*;
 
 const TITLE_TEXT: &str = "repos - v2022.7.4";
 const HELP_TEXT: &str = r#"CLI util to manage all git repositories in a specific directory.
-The root of the repos is coming from the "DEVDIR" env var
-or the first argument."#;
+The roots of the repos are coming from the arguments, the workspaces
+in the config file or the "DEVDIR" env var (":" separated)."#;
 
-pub fn get_root_path() -> ReposResult<PathBuf> {
+pub struct Args {
+    pub command: SubCommand,
+    pub sources: Vec<RepoSource>,
+    pub config: Config,
+}
+
+pub enum SubCommand {
+    Tui,
+    List(ListOptions),
+    Fetch,
+    Pull,
+    Switch(SwitchOptions),
+    Exec(ExecOptions),
+    ConfigShow,
+}
+
+pub struct SearchOptions {
+    // How many directory levels below the root are searched for repos.
+    pub max_depth: usize,
+    // Directories matching any of these are not searched.
+    pub excludes: Vec<glob::Pattern>,
+}
+
+// Where repos are coming from. Every source is a group in the tui.
+pub enum RepoSource {
+    Root(PathBuf, SearchOptions),
+    // Repos listed explicitly in a workspace.
+    Repos(String, Vec<PathBuf>),
+}
+
+impl RepoSource {
+    pub fn title(&self) -> String {
+        match self {
+            Self::Root(path, _) => path.display().to_string(),
+            Self::Repos(name, _) => name.to_string(),
+        }
+    }
+
+    pub fn find_repos(&self) -> ReposResult<Vec<PathBuf>> {
+        match self {
+            Self::Root(path, search) => find_git_repos_in_dir(path, search),
+            Self::Repos(_, paths) => Ok(paths.clone()),
+        }
+    }
+}
+
+pub fn get_args() -> ReposResult<Args> {
     let matches = ClapCommand::new(TITLE_TEXT)
-        .arg(Arg::new("rootpath"))
+        .subcommand_precedence_over_arg(true)
+        .arg(Arg::new("rootpath").multiple_values(true))
+        .arg(
+            Arg::new("workspace")
+                .long("workspace")
+                .global(true)
+                .short('w')
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help("Workspace from the config file. Can be repeated."),
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .global(true)
+                .takes_value(true)
+                .help("Config file. Default: ~/.config/repos/config.toml"),
+        )
+        .arg(
+            Arg::new("depth")
+                .long("depth")
+                .global(true)
+                .short('d')
+                .takes_value(true)
+                .help("Max directory depth to search for repos."),
+        )
+        .arg(
+            Arg::new("exclude")
+                .long("exclude")
+                .global(true)
+                .short('e')
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help("Glob of directories to skip, like \"node_modules\". Can be repeated."),
+        )
+        .arg(
+            Arg::new("update-delay")
+                .long("update-delay")
+                .global(true)
+                .takes_value(true)
+                .help("Seconds between refreshing the repos."),
+        )
+        .arg(
+            Arg::new("name-width")
+                .long("name-width")
+                .global(true)
+                .takes_value(true)
+                .help("Width of the repo name column."),
+        )
+        .arg(
+            Arg::new("branch-width")
+                .long("branch-width")
+                .global(true)
+                .takes_value(true)
+                .help("Max width of a branch name."),
+        )
+        .arg(
+            Arg::new("include-ignored")
+                .long("include-ignored")
+                .global(true)
+                .takes_value(true)
+                .possible_values(["true", "false"])
+                .help("Show ignored files in the status."),
+        )
+        .arg(
+            Arg::new("ahead-behind-all-branches")
+                .long("ahead-behind-all-branches")
+                .global(true)
+                .takes_value(true)
+                .possible_values(["true", "false"])
+                .help("Show ahead/behind for every local branch."),
+        )
+        .arg(
+            Arg::new("auto-stash")
+                .long("auto-stash")
+                .global(true)
+                .takes_value(true)
+                .possible_values(["true", "false"])
+                .help("Stash the local changes when leaving a branch, and apply them when coming back."),
+        )
+        .arg(
+            Arg::new("log-length")
+                .long("log-length")
+                .global(true)
+                .takes_value(true)
+                .help("How many commits the log pane shows."),
+        )
+        .arg(
+            Arg::new("workers")
+                .long("workers")
+                .global(true)
+                .takes_value(true)
+                .help("How many repos are fetched, pulled... at the same time."),
+        )
+        .arg(
+            Arg::new("dev-dir-env-var")
+                .long("dev-dir-env-var")
+                .global(true)
+                .takes_value(true)
+                .help("Env var with the roots, used without root args."),
+        )
+        .subcommand(
+            ClapCommand::new("list")
+                .about("Print the repos without the tui.")
+                .arg(Arg::new("rootpath").multiple_values(true))
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .short('f')
+                        .takes_value(true)
+                        .possible_values(["table", "json", "csv"])
+                        .default_value("table"),
+                )
+                .arg(
+                    Arg::new("fail-on-dirty")
+                        .long("fail-on-dirty")
+                        .help("Exit with an error if any repo is dirty."),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("fetch")
+                .about("Fetch the remotes of every repo.")
+                .arg(Arg::new("rootpath").multiple_values(true)),
+        )
+        .subcommand(
+            ClapCommand::new("pull")
+                .about("Fast-forward the current branch of every clean repo.")
+                .arg(Arg::new("rootpath").multiple_values(true))
+                .arg(
+                    Arg::new("ff-only")
+                        .long("ff-only")
+                        .help("Only fast-forward. This is the only way to pull for now."),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("switch")
+                .about("Checkout a branch in every repo that has it.")
+                .arg(Arg::new("branch").required(true).help("The branch to checkout."))
+                .arg(Arg::new("rootpath").multiple_values(true))
+                .arg(
+                    Arg::new("create")
+                        .long("create")
+                        .short('c')
+                        .help("Create the branch from HEAD in the repos that don't have it."),
+                )
+                .arg(
+                    Arg::new("rollback")
+                        .long("rollback")
+                        .help("Switch every repo back to its previous branch if any checkout fails."),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("exec")
+                .about("Run a command in every repo, like: repos exec -- git status --short")
+                .arg(Arg::new("rootpath").multiple_values(true))
+                .arg(
+                    Arg::new("command")
+                        .last(true)
+                        .required(true)
+                        .multiple_values(true)
+                        .help("The command and its arguments, after --."),
+                ),
+        )
+        .subcommand(
+            ClapCommand::new("config")
+                .about("Config file commands.")
+                .subcommand_required(true)
+                .subcommand(ClapCommand::new("show").about("Print the effective config.")),
+        )
         .about(HELP_TEXT)
         .get_matches();
-    if let Some(rootdir) = matches.value_of("rootpath") {
-        let mut buff = PathBuf::new();
-        buff.push(rootdir);
+    let mut config = Config::load(matches.value_of("config").map(Path::new))?;
+    config.apply_overrides(ConfigOverrides {
+        dev_dir_env_var: matches.value_of("dev-dir-env-var").map(String::from),
+        update_delay_secs: parse_flag(&matches, "update-delay")?,
+        repo_name_width: parse_flag(&matches, "name-width")?,
+        max_branch_name_width: parse_flag(&matches, "branch-width")?,
+        include_ignored: parse_flag(&matches, "include-ignored")?,
+        depth: parse_flag(&matches, "depth")?,
+        workers: parse_flag(&matches, "workers")?,
+        auto_stash: parse_flag(&matches, "auto-stash")?,
+        log_length: parse_flag(&matches, "log-length")?,
+        ahead_behind_all_branches: parse_flag(&matches, "ahead-behind-all-branches")?,
+        exclude: matches
+            .values_of("exclude")
+            .map(|values| values.map(String::from).collect()),
+    })?;
+    let command = match matches.subcommand() {
+        Some(("list", list_matches)) => SubCommand::List(ListOptions {
+            format: list_matches.value_of_t("format")?,
+            fail_on_dirty: list_matches.is_present("fail-on-dirty"),
+        }),
+        Some(("fetch", _)) => SubCommand::Fetch,
+        Some(("pull", _)) => SubCommand::Pull,
+        Some(("switch", switch_matches)) => SubCommand::Switch(SwitchOptions {
+            branch: switch_matches.value_of("branch").unwrap_or_default().to_string(),
+            create: switch_matches.is_present("create"),
+            rollback: switch_matches.is_present("rollback"),
+            auto_stash: config.auto_stash,
+        }),
+        Some(("exec", exec_matches)) => SubCommand::Exec(ExecOptions {
+            command: exec_matches.values_of("command").unwrap_or_default().map(String::from).collect(),
+        }),
+        Some(("config", _)) => SubCommand::ConfigShow,
+        _ => SubCommand::Tui,
+    };
+    // Roots can be passed before or after the subcommand.
+    let mut rootdirs = matches.values_of("rootpath").unwrap_or_default().collect::<Vec<_>>();
+    if let Some((name, sub_matches)) = matches.subcommand() {
+        if name != "config" {
+            rootdirs.extend(sub_matches.values_of("rootpath").unwrap_or_default());
+        }
+    }
+    let sources = match command {
+        SubCommand::ConfigShow => vec![],
+        _ => get_sources(
+            &config,
+            matches.values_of("workspace").unwrap_or_default().collect(),
+            rootdirs,
+        )?,
+    };
+    Ok(Args { command, sources, config })
+}
+
+// Like "Invalid value "abc" for --depth: invalid digit found in string".
+fn parse_flag<T>(matches: &clap::ArgMatches, name: &str) -> ReposResult<Option<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    matches
+        .value_of(name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|error| Error::other(format!(r#"Invalid value "{}" for --{}: {}"#, value, name, error)).into())
+        })
+        .transpose()
+}
+
+fn get_sources(config: &Config, workspaces: Vec<&str>, rootdirs: Vec<&str>) -> ReposResult<Vec<RepoSource>> {
+    let root_source = |path: PathBuf| -> ReposResult<RepoSource> {
+        let search = config.search_options(&path)?;
+        Ok(RepoSource::Root(path, search))
+    };
+    let mut sources = vec![];
+    for name in workspaces {
+        let workspace = config.workspace(name)?;
+        for root in &workspace.roots {
+            sources.push(root_source(get_dir_path(&expand_home(root), "Workspace root")?)?);
+        }
+        if !workspace.repos.is_empty() {
+            let repos = workspace
+                .repos
+                .iter()
+                .map(|repo| get_dir_path(&expand_home(repo), "Workspace repo"))
+                .collect::<ReposResult<_>>()?;
+            sources.push(RepoSource::Repos(name.to_string(), repos));
+        }
+    }
+    for rootdir in rootdirs {
         let path_arg = Path::new(rootdir);
         if !path_arg.is_dir() {
-            return Err(Box::new(Error::new(
-                ErrorKind::Other,
-                "Path argument is not a directory.",
-            )));
+            return Err(Box::new(Error::other(format!(
+                r#"Path argument: "{}" is not a directory."#,
+                rootdir
+            ))));
         }
-        Ok(path_arg.canonicalize()?)
-    } else {
-        let devdir = var(DEV_DIR_ENV_VAR)?;
-        let path_arg = Path::new(&devdir);
-        if !path_arg.is_dir() {
-            return Err(Box::new(Error::new(
-                ErrorKind::Other,
-                format!(
-                    r#"Dir in dev env var: "{}" is not a directory."#,
-                    DEV_DIR_ENV_VAR
-                ),
-            )));
+        sources.push(root_source(path_arg.canonicalize()?)?);
+    }
+    if sources.is_empty() {
+        let devdir = var(&config.dev_dir_env_var)?;
+        for path in std::env::split_paths(&devdir) {
+            if !path.is_dir() {
+                return Err(Box::new(Error::other(format!(
+                    r#"Dir in dev env var: "{}" is not a directory: "{}"."#,
+                    config.dev_dir_env_var,
+                    path.display()
+                ))));
+            }
+            sources.push(root_source(path.canonicalize()?)?);
         }
-        Ok(path_arg.canonicalize()?)
     }
+    Ok(sources)
 }
 
-pub fn find_git_repos_in_dir(root: &Path) -> ReposResult<Vec<PathBuf>> {
-    let entries = root
-        .read_dir()?
-        .map(|res| res.map(|e| e.path()))
-        .collect::<Result<Vec<_>, io::Error>>()?;
-    let entries = entries
-        .into_iter()
-        .filter(|p| Path::new(&p).join(".git").is_dir())
-        .collect::<Vec<PathBuf>>();
-    Ok(entries)
+// The label is like "Workspace root", for the error message.
+fn get_dir_path(path: &Path, label: &str) -> ReposResult<PathBuf> {
+    if !path.is_dir() {
+        return Err(Box::new(Error::other(format!(
+            r#"{}: "{}" is not a directory."#,
+            label,
+            path.display()
+        ))));
+    }
+    Ok(path.canonicalize()?)
 }
 
-pub fn text_to_width(string: &str, limit: &usize) -> String {
-    if string.len() >= *limit {
-        format!("{}~", &string[0..limit - 1])
-    } else {
-        format!("{:<w$}", string, w = limit)
+pub fn find_git_repos_in_dir(root: &Path, search: &SearchOptions) -> ReposResult<Vec<PathBuf>> {
+    let mut repos = vec![];
+    find_git_repos_below(root, root, 1, search, &mut repos, true)?;
+    repos.sort();
+    Ok(repos)
+}
+
+// Repos are not searched for inside other repos.
+// Only errors reading the root itself are reported,
+// unreadable subdirectories are skipped.
+fn find_git_repos_below(
+    root: &Path,
+    dir: &Path,
+    depth: usize,
+    search: &SearchOptions,
+    repos: &mut Vec<PathBuf>,
+    is_root: bool,
+) -> ReposResult<()> {
+    if depth > search.max_depth {
+        return Ok(());
+    }
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(error) if is_root => return Err(Box::new(error)),
+        Err(_) => return Ok(()),
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_dir() || is_excluded(root, &path, search) {
+            continue;
+        }
+        if is_git_repo(&path) {
+            repos.push(path);
+        } else {
+            find_git_repos_below(root, &path, depth + 1, search, repos, false)?;
+        }
+    }
+    Ok(())
+}
+
+// A ".git" dir is a regular repo, a ".git" file points to the git dir
+// of a linked worktree or a submodule. Bare repos have no ".git" at all,
+// the repo files are directly in the dir.
+fn is_git_repo(path: &Path) -> bool {
+    let dot_git = path.join(".git");
+    if dot_git.is_dir() || dot_git.is_file() {
+        return true;
     }
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
+
+// The last component of the path, like "api" for "/dev/work/api".
+pub fn repo_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .to_string()
 }
 
+fn is_excluded(root: &Path, path: &Path, search: &SearchOptions) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    search
+        .excludes
+        .iter()
+        .any(|pattern| pattern.matches(name) || pattern.matches_path(relative))
+}
+
+pub fn text_to_width(string: &str, limit: &usize) -> String {
+    format!("{:<w$}", limit_text(string, limit), w = limit)
+}
+
+// Counts chars, not bytes, so names like "José" aren't cut in half.
 pub fn limit_text(string: &str, limit: &usize) -> String {
-    if string.len() >= *limit {
-        format!("{}~", &string[0..limit - 1])
+    if string.chars().count() >= *limit {
+        format!("{}~", string.chars().take(limit.saturating_sub(1)).collect::<String>())
     } else {
         string.to_string()
     }
 }
 
+// Like "5 minutes ago".
+pub fn format_time_ago(seconds: i64) -> String {
+    let units = [
+        (60 * 60 * 24 * 365, "year"),
+        (60 * 60 * 24 * 30, "month"),
+        (60 * 60 * 24 * 7, "week"),
+        (60 * 60 * 24, "day"),
+        (60 * 60, "hour"),
+        (60, "minute"),
+    ];
+    for (unit_seconds, name) in units {
+        let count = seconds / unit_seconds;
+        if count > 0 {
+            let plural = if count == 1 { "" } else { "s" };
+            return format!("{} {}{} ago", count, name, plural);
+        }
+    }
+    "just now".to_string()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     #[test]
     fn limiting_string_length() {
-        assert_eq!(text_to_width(&String::new(), &10).len(), 10);
+        assert_eq!(text_to_width("", &10).len(), 10);
+    }
+
+    #[test]
+    fn limiting_non_ascii_text_by_chars() {
+        assert_eq!(text_to_width("José Álvarez", &6), "José ~");
+        assert_eq!(limit_text("Ångström", &20), "Ångström");
+        assert_eq!(limit_text("Ångström", &3), "Ån~");
+    }
+
+    #[test]
+    fn formatting_time_ago() {
+        assert_eq!(format_time_ago(30), "just now");
+        assert_eq!(format_time_ago(60), "1 minute ago");
+        assert_eq!(format_time_ago(60 * 60 * 5), "5 hours ago");
+        assert_eq!(format_time_ago(60 * 60 * 24 * 400), "1 year ago");
+    }
+
+    fn search(max_depth: usize, excludes: &[&str]) -> SearchOptions {
+        SearchOptions {
+            max_depth,
+            excludes: excludes.iter().map(|e| glob::Pattern::new(e).unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn finding_nested_repos() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["flat", "clients/acme/api", "clients/acme/api/vendor/lib", "deep/a/b/c/d"] {
+            std::fs::create_dir_all(root.path().join(dir).join(".git")).unwrap();
+        }
+        let found = find_git_repos_in_dir(root.path(), &search(3, &[])).unwrap();
+        assert_eq!(
+            found,
+            vec![root.path().join("clients/acme/api"), root.path().join("flat")]
+        );
+        let found = find_git_repos_in_dir(root.path(), &search(1, &[])).unwrap();
+        assert_eq!(found, vec![root.path().join("flat")]);
+    }
+
+    #[test]
+    fn excluding_dirs_from_search() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["app", "node_modules/pkg", "clients/old/repo", "clients/new/repo"] {
+            std::fs::create_dir_all(root.path().join(dir).join(".git")).unwrap();
+        }
+        let found =
+            find_git_repos_in_dir(root.path(), &search(3, &["node_modules", "clients/old"])).unwrap();
+        assert_eq!(
+            found,
+            vec![root.path().join("app"), root.path().join("clients/new/repo")]
+        );
+    }
+
+    #[test]
+    fn naming_the_flag_of_a_bad_value() {
+        let matches = ClapCommand::new("test")
+            .arg(Arg::new("depth").long("depth").takes_value(true))
+            .get_matches_from(["test", "--depth", "abc"]);
+        let error = parse_flag::<usize>(&matches, "depth").unwrap_err().to_string();
+        assert!(error.starts_with(r#"Invalid value "abc" for --depth: "#), "{}", error);
+        let matches = ClapCommand::new("test")
+            .arg(Arg::new("depth").long("depth").takes_value(true))
+            .get_matches_from(["test", "--depth", "4"]);
+        assert_eq!(parse_flag::<usize>(&matches, "depth").unwrap(), Some(4));
+    }
+
+    #[test]
+    fn getting_sources_from_workspaces_and_args() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["work", "infra", "oss", "shared"] {
+            std::fs::create_dir(root.path().join(dir)).unwrap();
+        }
+        let root_path = root.path().canonicalize().unwrap();
+        let config = Config::parse(&format!(
+            r#"
+            [workspaces.work]
+            roots = ["{0}/work", "{0}/infra"]
+            repos = ["{0}/shared"]
+
+            [workspaces.broken]
+            repos = ["{0}/missing"]
+            "#,
+            root_path.display()
+        ))
+        .unwrap();
+        let oss = root_path.join("oss");
+        let sources = get_sources(&config, vec!["work"], vec![oss.to_str().unwrap()]).unwrap();
+        assert_eq!(
+            sources.iter().map(|s| s.title()).collect::<Vec<_>>(),
+            vec![
+                root_path.join("work").display().to_string(),
+                root_path.join("infra").display().to_string(),
+                "work".to_string(),
+                oss.display().to_string(),
+            ]
+        );
+        match &sources[2] {
+            RepoSource::Repos(_, repos) => assert_eq!(repos, &vec![root_path.join("shared")]),
+            RepoSource::Root(..) => panic!("not a workspace repo list"),
+        }
+        assert!(get_sources(&config, vec!["missing"], vec![]).is_err());
+        match get_sources(&config, vec!["broken"], vec![]) {
+            Err(error) => assert!(error.to_string().starts_with("Workspace repo: ")),
+            Ok(_) => panic!("a missing workspace repo is not an error"),
+        }
+    }
+
+    #[test]
+    fn finding_worktrees_gitfiles_and_bare_repos() {
+        let root = tempfile::tempdir().unwrap();
+        let main = crate::testutils::init_repo(&root.path().join("main"));
+        main.worktree("linked", &root.path().join("linked"), None).unwrap();
+        crate::testutils::init_gitfile_repo(&root.path().join("sub"), &root.path().join("modules/sub"));
+        Repository::init_bare(root.path().join("bare.git")).unwrap();
+        let found = find_git_repos_in_dir(root.path(), &search(3, &["modules"])).unwrap();
+        assert_eq!(
+            found,
+            ["bare.git", "linked", "main", "sub"].map(|d| root.path().join(d)).to_vec()
+        );
     }
 }